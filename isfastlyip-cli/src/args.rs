@@ -0,0 +1,103 @@
+//! The command line conventions shared by every subcommand: short and long
+//! flags, values given as `--flag value` or `--flag=value`, and `--` ending
+//! the options.
+
+use anyhow::{bail, Result};
+
+/// One argument of the command line.
+#[derive(Debug, PartialEq)]
+pub enum Arg {
+    /// An option such as `-l` or `--list`, without any inline `=value`.
+    Flag(String),
+    /// Anything else, including `-` for stdin and everything after `--`.
+    Operand(String),
+}
+
+/// Walks the arguments, handing out the value of a flag when asked for it.
+pub struct Args<I> {
+    args: I,
+    /// The last flag, named in errors about its value.
+    flag: String,
+    /// The `=value` given with the last flag.
+    inline_value: Option<String>,
+    operands_only: bool,
+}
+
+impl<I: Iterator<Item = String>> Args<I> {
+    pub fn new(args: I) -> Self {
+        Args {
+            args,
+            flag: String::new(),
+            inline_value: None,
+            operands_only: false,
+        }
+    }
+
+    /// The value of the last flag, given inline or as the next argument.
+    pub fn value(&mut self) -> Result<String> {
+        match self.inline_value.take().or_else(|| self.args.next()) {
+            Some(value) => Ok(value),
+            None => bail!("{} needs a value", self.flag),
+        }
+    }
+}
+
+impl<I: Iterator<Item = String>> Iterator for Args<I> {
+    type Item = Arg;
+
+    fn next(&mut self) -> Option<Arg> {
+        let arg = self.args.next()?;
+        if self.operands_only || !arg.starts_with('-') || arg == "-" {
+            return Some(Arg::Operand(arg));
+        }
+        if arg == "--" {
+            self.operands_only = true;
+            return self.next();
+        }
+        let (flag, inline_value) = match arg.find('=') {
+            Some(eq) if arg.starts_with("--") => {
+                (arg[..eq].to_owned(), Some(arg[eq + 1..].to_owned()))
+            }
+            _ => (arg, None),
+        };
+        self.flag = flag.clone();
+        self.inline_value = inline_value;
+        Some(Arg::Flag(flag))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &[&str]) -> Args<std::vec::IntoIter<String>> {
+        Args::new(
+            line.iter()
+                .map(|arg| (*arg).to_owned())
+                .collect::<Vec<_>>()
+                .into_iter(),
+        )
+    }
+
+    #[test]
+    fn flags_and_values() {
+        let mut args = args(&["-l", "list.json", "--format=csv", "-", "--", "-q"]);
+        assert_eq!(args.next(), Some(Arg::Flag("-l".to_owned())));
+        assert_eq!(args.value().unwrap(), "list.json");
+        assert_eq!(args.next(), Some(Arg::Flag("--format".to_owned())));
+        assert_eq!(args.value().unwrap(), "csv");
+        assert_eq!(args.next(), Some(Arg::Operand("-".to_owned())));
+        assert_eq!(args.next(), Some(Arg::Operand("-q".to_owned())));
+        assert_eq!(args.next(), None);
+    }
+
+    #[test]
+    fn missing_value() {
+        let mut args = args(&["--list"]);
+        args.next();
+        assert_eq!(
+            args.value().unwrap_err().to_string(),
+            "--list needs a value"
+        );
+    }
+}
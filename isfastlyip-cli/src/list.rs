@@ -0,0 +1,76 @@
+use anyhow::{Context, Result};
+use isfastlyip::validate::{self, LoadedList};
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const LIST_URL: &str = "https://api.fastly.com/public-ip-list";
+
+/// Where `--update` keeps the fetched list: `$ISFASTLYIP_CACHE`, or else
+/// `isfastlyip/public-ip-list` under `$XDG_CACHE_HOME` or `~/.cache`.
+pub fn cache_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("ISFASTLYIP_CACHE") {
+        return Some(PathBuf::from(path));
+    }
+    let cache_dir = match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+    Some(cache_dir.join("isfastlyip").join("public-ip-list"))
+}
+
+/// Reads a `public-ip-list` JSON file, skipping entries that are not
+/// networks like the service does.
+fn read(path: &Path) -> Result<LoadedList> {
+    let body = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let validated = validate::validate_response(200, None, &body)
+        .with_context(|| format!("{} is not a usable IP list", path.display()))?;
+    Ok(LoadedList::live(validated))
+}
+
+/// Fetches the published list and stores it at `path` once it has been
+/// validated.
+fn update(path: &Path) -> Result<()> {
+    let resp = ureq::get(LIST_URL)
+        .timeout(Duration::from_secs(30))
+        .call()
+        .with_context(|| format!("Failed to fetch {}", LIST_URL))?;
+    let status = resp.status();
+    let content_type = resp.header("content-type").map(str::to_owned);
+    let mut body = Vec::new();
+    resp.into_reader().take(16 << 20).read_to_end(&mut body)?;
+    validate::validate_response(status, content_type.as_deref(), &body)?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let partial = path.with_extension("partial");
+    fs::write(&partial, &body)?;
+    fs::rename(&partial, path).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(())
+}
+
+/// Loads the list from `file` when given, or else from the cached copy,
+/// refreshing it first when `refresh` is set. Without either, the snapshot
+/// compiled into the binary is used.
+pub fn load(file: Option<&Path>, refresh: bool) -> Result<LoadedList> {
+    if let Some(file) = file {
+        return read(file);
+    }
+
+    let cache = cache_path();
+    if refresh {
+        let path = cache
+            .as_ref()
+            .context("No cache directory, set ISFASTLYIP_CACHE")?;
+        update(path)?;
+    }
+    match cache {
+        Some(path) if path.exists() => read(&path),
+        _ => Ok(LoadedList::snapshot(
+            "No cached copy of the list, run with --update to fetch one".to_owned(),
+        )),
+    }
+}
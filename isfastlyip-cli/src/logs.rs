@@ -0,0 +1,537 @@
+//! `isfastlyip logs`, annotating the client addresses of access logs one line
+//! at a time, so that files of any size are read in a single pass with only
+//! the current line in memory.
+
+use anyhow::{bail, Context, Result};
+use isfastlyip::{addr, service, IpMatcher};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::IpAddr;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use crate::args::{Arg, Args};
+
+pub const USAGE: &str = "\
+Usage: isfastlyip logs [OPTIONS] [FILE]...
+
+Annotates access logs in the common, combined or JSON format with whether
+their client address is Fastly's, or keeps only the lines of Fastly or other
+traffic. Reads stdin when no file, or `-`, is given.
+
+Options:
+  -l, --list FILE       Check against a public-ip-list JSON file
+  -u, --update          Fetch the published list into the cache first
+  -c, --column N        The field of text lines holding the client address,
+                        counted from 1 [default: 1]
+  -p, --pointer PATH    The JSON pointer to the client address of JSON lines
+                        [default: /client_ip]
+  -o, --only KIND       Print only the lines of `fastly` or `other` traffic,
+                        unannotated
+  -q, --quiet           Do not print the summary
+  -h, --help            Print this help
+
+Lines starting with `{` are read as JSON objects, which get an
+\"is_fastly_ip\" member, or have the value of the one they have replaced, and
+other lines as whitespace-separated fields, in which quoted strings and
+bracketed timestamps are one field each, which get is_fastly_ip=true or false
+appended. Lines without a readable address are annotated as unknown and never
+kept by --only. Lines longer than 64 KiB are dropped and counted as unknown.
+";
+
+/// The longest line read, in bytes, so that a file without newlines is not
+/// read into memory whole.
+pub const MAX_LINE: u64 = 64 * 1024;
+
+/// The traffic `--only` keeps.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Only {
+    Fastly,
+    Other,
+}
+
+#[derive(Debug)]
+pub struct LogOptions {
+    pub list: Option<PathBuf>,
+    pub update: bool,
+    pub column: usize,
+    pub pointer: String,
+    pub only: Option<Only>,
+    pub quiet: bool,
+    pub files: Vec<PathBuf>,
+}
+
+/// Reads the arguments following `logs`, or returns `None` when help was
+/// asked for.
+pub fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Option<LogOptions>> {
+    let mut options = LogOptions {
+        list: None,
+        update: false,
+        column: 1,
+        pointer: "/client_ip".to_owned(),
+        only: None,
+        quiet: false,
+        files: Vec::new(),
+    };
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        let flag = match arg {
+            Arg::Flag(flag) => flag,
+            Arg::Operand(file) => {
+                options.files.push(PathBuf::from(file));
+                continue;
+            }
+        };
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-l" | "--list" => options.list = Some(PathBuf::from(args.value()?)),
+            "-u" | "--update" => options.update = true,
+            "-c" | "--column" => {
+                options.column = match args.value()?.parse() {
+                    Ok(column) if column > 0 => column,
+                    _ => bail!("--column needs a field number counted from 1"),
+                }
+            }
+            "-p" | "--pointer" => {
+                let pointer = args.value()?;
+                if !pointer.is_empty() && !pointer.starts_with('/') {
+                    bail!("JSON pointer {:?} does not start with /", pointer);
+                }
+                options.pointer = pointer;
+            }
+            "-o" | "--only" => {
+                options.only = match args.value()?.as_str() {
+                    "fastly" => Some(Only::Fastly),
+                    "other" => Some(Only::Other),
+                    kind => bail!("--only takes fastly or other, not {:?}", kind),
+                }
+            }
+            "-q" | "--quiet" => options.quiet = true,
+            _ => bail!("Unknown option {}", flag),
+        }
+    }
+    Ok(Some(options))
+}
+
+/// The `n`th field of a text log line, counted from 1. Quoted strings, with
+/// backslash escapes, and bracketed timestamps are single fields.
+fn field(line: &str, n: usize) -> Option<&str> {
+    let bytes = line.as_bytes();
+    let mut index = 0;
+    let mut count = 0;
+    loop {
+        while index < bytes.len() && bytes[index].is_ascii_whitespace() {
+            index += 1;
+        }
+        if index == bytes.len() {
+            return None;
+        }
+
+        let start = index;
+        let (value_start, value_end) = match bytes[index] {
+            quote @ b'"' | quote @ b'[' => {
+                let close = if quote == b'"' { b'"' } else { b']' };
+                index += 1;
+                while index < bytes.len() && bytes[index] != close {
+                    index += if bytes[index] == b'\\' { 2 } else { 1 };
+                }
+                let end = index.min(bytes.len());
+                index = (index + 1).min(bytes.len());
+                (start + 1, end)
+            }
+            _ => {
+                while index < bytes.len() && !bytes[index].is_ascii_whitespace() {
+                    index += 1;
+                }
+                (start, index)
+            }
+        };
+
+        count += 1;
+        if count == n {
+            return line.get(value_start..value_end);
+        }
+    }
+}
+
+/// Reads a client address as logs write it: bare, bracketed or with a port,
+/// or as the first entry of an `X-Forwarded-For` style list.
+fn client_addr(value: &str) -> Option<IpAddr> {
+    let value = value.split(',').next()?.trim();
+    if let Ok(addr) = addr::parse_addr(value) {
+        return Some(addr);
+    }
+    if let Some(rest) = value.strip_prefix('[') {
+        let end = rest.find(']')?;
+        return addr::parse_addr(&rest[..end]).ok();
+    }
+    match value.rfind(':') {
+        Some(colon) if value.find(':') == Some(colon) => addr::parse_addr(&value[..colon]).ok(),
+        _ => None,
+    }
+}
+
+/// The client address of one line, if it has a readable one.
+fn line_addr(line: &str, options: &LogOptions) -> Option<IpAddr> {
+    if line.trim_start().starts_with('{') {
+        let json: serde_json::Value = serde_json::from_str(line).ok()?;
+        client_addr(json.pointer(&options.pointer)?.as_str()?)
+    } else {
+        client_addr(field(line, options.column)?)
+    }
+}
+
+fn trim_end(bytes: &[u8]) -> &[u8] {
+    let len = bytes.len()
+        - bytes
+            .iter()
+            .rev()
+            .take_while(|byte| byte.is_ascii_whitespace())
+            .count();
+    &bytes[..len]
+}
+
+/// The byte range of the value of a top-level member of a JSON object. Only
+/// strings and nesting are tracked, so that the rest of the line is kept as
+/// it is.
+fn member_value(object: &[u8], name: &str) -> Option<Range<usize>> {
+    let key = format!("\"{}\"", name);
+    let mut depth = 0;
+    let mut value_start = None;
+    let mut index = 0;
+    while index < object.len() {
+        match object[index] {
+            b'"' => {
+                let start = index;
+                index += 1;
+                while index < object.len() && object[index] != b'"' {
+                    index += if object[index] == b'\\' { 2 } else { 1 };
+                }
+                index += 1;
+                let is_key = depth == 1 && object.get(start..index) == Some(key.as_bytes());
+                if is_key && value_start.is_none() {
+                    let colon = index
+                        + object[index.min(object.len())..]
+                            .iter()
+                            .take_while(|byte| byte.is_ascii_whitespace())
+                            .count();
+                    if object.get(colon) == Some(&b':') {
+                        value_start = Some(colon + 1);
+                        index = colon + 1;
+                    }
+                }
+                continue;
+            }
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' | b',' if depth == 1 && value_start.is_some() => {
+                let start = value_start?;
+                let value = &object[start..index];
+                let leading = value
+                    .iter()
+                    .take_while(|byte| byte.is_ascii_whitespace())
+                    .count();
+                return Some(start + leading..start + trim_end(value).len());
+            }
+            b'}' | b']' => depth -= 1,
+            _ => {}
+        }
+        index += 1;
+    }
+    None
+}
+
+/// Appends a line, without its line ending, and its annotation to `out`.
+/// The bytes of the line are kept as they are, even when not UTF-8, except
+/// for the value of an `is_fastly_ip` member it already has.
+fn annotate(line: &[u8], is_fastly_ip: Option<bool>, out: &mut Vec<u8>) {
+    let trimmed = trim_end(line);
+    let is_object = trimmed.last() == Some(&b'}')
+        && trimmed.iter().find(|byte| !byte.is_ascii_whitespace()) == Some(&b'{');
+    if is_object {
+        let value = match is_fastly_ip {
+            Some(true) => "true",
+            Some(false) => "false",
+            None => "null",
+        };
+        if let Some(existing) = member_value(trimmed, "is_fastly_ip") {
+            out.extend_from_slice(&trimmed[..existing.start]);
+            out.extend_from_slice(value.as_bytes());
+            out.extend_from_slice(&trimmed[existing.end..]);
+            return;
+        }
+        let members = trim_end(&trimmed[..trimmed.len() - 1]);
+        out.extend_from_slice(members);
+        if members.last() != Some(&b'{') {
+            out.push(b',');
+        }
+        out.extend_from_slice(format!("\"is_fastly_ip\":{}}}", value).as_bytes());
+    } else {
+        let value = match is_fastly_ip {
+            Some(true) => "true",
+            Some(false) => "false",
+            None => "unknown",
+        };
+        out.extend_from_slice(line);
+        out.extend_from_slice(format!(" is_fastly_ip={}", value).as_bytes());
+    }
+}
+
+/// Counts of the lines processed, printed when done.
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub lines: u64,
+    pub fastly: u64,
+    pub other: u64,
+    pub unknown: u64,
+}
+
+/// Discards the rest of the current line.
+fn skip_line<R: BufRead>(input: &mut R) -> io::Result<()> {
+    loop {
+        let available = input.fill_buf()?;
+        if available.is_empty() {
+            return Ok(());
+        }
+        match available.iter().position(|byte| *byte == b'\n') {
+            Some(end) => {
+                input.consume(end + 1);
+                return Ok(());
+            }
+            None => {
+                let len = available.len();
+                input.consume(len);
+            }
+        }
+    }
+}
+
+/// Annotates or filters every line of `input` into `output`.
+pub fn process<R: BufRead, W: Write>(
+    mut input: R,
+    output: &mut W,
+    matcher: &IpMatcher,
+    options: &LogOptions,
+    summary: &mut Summary,
+) -> Result<()> {
+    let mut buf = Vec::new();
+    let mut out = Vec::new();
+    loop {
+        buf.clear();
+        if Read::take(&mut input, MAX_LINE).read_until(b'\n', &mut buf)? == 0 {
+            return Ok(());
+        }
+        if buf.len() as u64 == MAX_LINE && buf.last() != Some(&b'\n') {
+            skip_line(&mut input)?;
+            summary.lines += 1;
+            summary.unknown += 1;
+            continue;
+        }
+        let content_len = buf.len()
+            - buf
+                .iter()
+                .rev()
+                .take_while(|byte| **byte == b'\n' || **byte == b'\r')
+                .count();
+        let line = String::from_utf8_lossy(&buf[..content_len]);
+        if line.trim().is_empty() {
+            output.write_all(&buf)?;
+            continue;
+        }
+
+        summary.lines += 1;
+        let is_fastly_ip =
+            line_addr(&line, options).map(|addr| service::check_ip(addr, matcher).is_fastly_ip);
+        match is_fastly_ip {
+            Some(true) => summary.fastly += 1,
+            Some(false) => summary.other += 1,
+            None => summary.unknown += 1,
+        }
+
+        match options.only {
+            Some(only) => {
+                if is_fastly_ip == Some(only == Only::Fastly) {
+                    output.write_all(&buf)?;
+                }
+            }
+            None => {
+                out.clear();
+                annotate(&buf[..content_len], is_fastly_ip, &mut out);
+                out.extend_from_slice(&buf[content_len..]);
+                output.write_all(&out)?;
+            }
+        }
+    }
+}
+
+/// Runs the `logs` subcommand over every file, or stdin.
+pub fn run(options: &LogOptions, matcher: &IpMatcher) -> Result<Summary> {
+    let stdout = io::stdout();
+    let mut output = BufWriter::new(stdout.lock());
+    let mut summary = Summary::default();
+
+    let stdin_only = [PathBuf::from("-")];
+    let files = if options.files.is_empty() {
+        &stdin_only[..]
+    } else {
+        &options.files[..]
+    };
+    for file in files {
+        if file.as_os_str() == "-" {
+            let stdin = io::stdin();
+            process(stdin.lock(), &mut output, matcher, options, &mut summary)?;
+        } else {
+            let input =
+                File::open(file).with_context(|| format!("Failed to open {}", file.display()))?;
+            process(
+                BufReader::with_capacity(1 << 16, input),
+                &mut output,
+                matcher,
+                options,
+                &mut summary,
+            )?;
+        }
+    }
+    output.flush()?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use isfastlyip::FastlyIpList;
+
+    const COMBINED: &str = r#"151.101.1.1 - - [10/Oct/2021:13:55:36 -0700] "GET /a b HTTP/1.1" 200 2326 "-" "curl/7.79""#;
+
+    fn options() -> LogOptions {
+        parse_args(std::iter::empty()).unwrap().unwrap()
+    }
+
+    fn run_lines(input: &str, options: &LogOptions) -> (String, Summary) {
+        let matcher = FastlyIpList::from_json(
+            br#"{"addresses": ["151.101.0.0/16"], "ipv6_addresses": ["2a04:4e42::/32"]}"#,
+        )
+        .unwrap()
+        .matcher()
+        .unwrap();
+        let mut output = Vec::new();
+        let mut summary = Summary::default();
+        process(
+            input.as_bytes(),
+            &mut output,
+            &matcher,
+            options,
+            &mut summary,
+        )
+        .unwrap();
+        (String::from_utf8(output).unwrap(), summary)
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(field(COMBINED, 1), Some("151.101.1.1"));
+        assert_eq!(field(COMBINED, 4), Some("10/Oct/2021:13:55:36 -0700"));
+        assert_eq!(field(COMBINED, 5), Some("GET /a b HTTP/1.1"));
+        assert_eq!(field(COMBINED, 9), Some("curl/7.79"));
+        assert_eq!(field(COMBINED, 10), None);
+        assert_eq!(field(r#"a "b \" c" d"#, 3), Some("d"));
+        assert_eq!(field(r#"a "unterminated"#, 2), Some("unterminated"));
+    }
+
+    #[test]
+    fn client_addrs() {
+        assert_eq!(client_addr("151.101.1.1:443"), "151.101.1.1".parse().ok());
+        assert_eq!(
+            client_addr("[2a04:4e42::1]:443"),
+            "2a04:4e42::1".parse().ok()
+        );
+        assert_eq!(client_addr("2a04:4e42::1"), "2a04:4e42::1".parse().ok());
+        assert_eq!(client_addr("8.8.8.8, 151.101.1.1"), "8.8.8.8".parse().ok());
+        assert_eq!(client_addr("-"), None);
+    }
+
+    #[test]
+    fn annotates_text_and_json() {
+        let input = format!(
+            "{}\n8.8.8.8 - - [-] \"GET /\" 200 1\r\n\n{{\"client_ip\": \"2a04:4e42::1\", \"status\": 200}}\n{{\"status\": 500}}\n- garbage",
+            COMBINED
+        );
+        let (output, summary) = run_lines(&input, &options());
+
+        assert_eq!(
+            output,
+            format!(
+                "{} is_fastly_ip=true\n8.8.8.8 - - [-] \"GET /\" 200 1 is_fastly_ip=false\r\n\n{{\"client_ip\": \"2a04:4e42::1\", \"status\": 200,\"is_fastly_ip\":true}}\n{{\"status\": 500,\"is_fastly_ip\":null}}\n- garbage is_fastly_ip=unknown",
+                COMBINED
+            )
+        );
+        assert_eq!(
+            summary,
+            Summary {
+                lines: 5,
+                fastly: 2,
+                other: 1,
+                unknown: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn skips_overlong_lines() {
+        let input = format!(
+            "{}\n{}\n8.8.8.8 x\n",
+            "151.101.1.1 ".repeat(MAX_LINE as usize),
+            COMBINED
+        );
+        let (output, summary) = run_lines(&input, &options());
+
+        assert_eq!(
+            output,
+            format!(
+                "{} is_fastly_ip=true\n8.8.8.8 x is_fastly_ip=false\n",
+                COMBINED
+            )
+        );
+        assert_eq!(
+            summary,
+            Summary {
+                lines: 3,
+                fastly: 1,
+                other: 1,
+                unknown: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn overwrites_existing_annotations() {
+        let input = concat!(
+            "{\"client_ip\": \"151.101.1.1\", \"is_fastly_ip\": false, \"n\": 1}\n",
+            "{\"client_ip\": \"8.8.8.8\", \"tags\": {\"is_fastly_ip\": 1}, \"is_fastly_ip\":\"yes\" }\n",
+            "{\"note\": \"is_fastly_ip\", \"client_ip\": \"8.8.8.8\"}\n",
+        );
+        let (output, _) = run_lines(input, &options());
+        assert_eq!(
+            output,
+            concat!(
+                "{\"client_ip\": \"151.101.1.1\", \"is_fastly_ip\": true, \"n\": 1}\n",
+                "{\"client_ip\": \"8.8.8.8\", \"tags\": {\"is_fastly_ip\": 1}, \"is_fastly_ip\":false }\n",
+                "{\"note\": \"is_fastly_ip\", \"client_ip\": \"8.8.8.8\",\"is_fastly_ip\":false}\n",
+            )
+        );
+    }
+
+    #[test]
+    fn filters() {
+        let input = "151.101.1.1 a\n8.8.8.8 b\n- c\n";
+        let mut options = options();
+        options.only = Some(Only::Other);
+        assert_eq!(run_lines(input, &options).0, "8.8.8.8 b\n");
+
+        options.only = Some(Only::Fastly);
+        options.column = 2;
+        assert_eq!(
+            run_lines("a 151.101.1.1\nb 8.8.8.8\n", &options).0,
+            "a 151.101.1.1\n"
+        );
+    }
+}
@@ -0,0 +1,212 @@
+//! `isfastlyip`, answering lookups the way the edge service does, without
+//! the HTTP round trip.
+
+mod args;
+mod list;
+mod logs;
+mod outcome;
+
+use anyhow::{bail, Context, Result};
+use isfastlyip::batch::BatchItem;
+use isfastlyip::render::{Format, Render};
+use isfastlyip::validate::LoadedList;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::args::{Arg, Args};
+use crate::outcome::{Outcome, Outcomes};
+
+const USAGE: &str = "\
+Usage: isfastlyip [OPTIONS] [QUERY]...
+       isfastlyip logs [OPTIONS] [FILE]...
+
+Checks whether addresses, CIDR networks or address ranges such as
+151.101.0.0-151.101.3.255 are Fastly's. Queries are read from stdin, one per
+line, when none are given or the only one is `-`.
+
+Options:
+  -l, --list FILE      Check against a public-ip-list JSON file
+  -u, --update         Fetch the published list into the cache first
+  -f, --format FORMAT  json, text, csv or ndjson [default: text]
+  -q, --quiet          Print nothing, only set the exit status
+  -h, --help           Print this help
+
+See `isfastlyip logs --help` for annotating access logs.
+
+Without --list, the copy cached by --update is used, or else the snapshot
+compiled into the binary. The cache is kept at $ISFASTLYIP_CACHE, or else
+under $XDG_CACHE_HOME or ~/.cache.
+
+Exit status: 0 when every query is entirely Fastly's, 1 when one is not, and
+2 when a query is invalid or the list cannot be loaded.
+";
+
+const EXIT_FASTLY: i32 = 0;
+const EXIT_NOT_FASTLY: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+
+#[derive(Debug)]
+struct Options {
+    list: Option<PathBuf>,
+    update: bool,
+    format: Format,
+    quiet: bool,
+    queries: Vec<String>,
+}
+
+/// Reads the command line, or returns `None` when help was asked for.
+fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Option<Options>> {
+    let mut options = Options {
+        list: None,
+        update: false,
+        format: Format::Text,
+        quiet: false,
+        queries: Vec::new(),
+    };
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        let flag = match arg {
+            Arg::Flag(flag) => flag,
+            Arg::Operand(query) => {
+                options.queries.push(query);
+                continue;
+            }
+        };
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-l" | "--list" => options.list = Some(PathBuf::from(args.value()?)),
+            "-u" | "--update" => options.update = true,
+            "-f" | "--format" => {
+                let name = args.value()?;
+                options.format = Format::from_name(&name)
+                    .with_context(|| format!("Unsupported format {:?}", name))?;
+            }
+            "-q" | "--quiet" => options.quiet = true,
+            _ => bail!("Unknown option {}", flag),
+        }
+    }
+    Ok(Some(options))
+}
+
+/// Reads queries from stdin, skipping blank lines and `#` comments.
+fn read_queries() -> Result<Vec<String>> {
+    let mut queries = Vec::new();
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let query = line.trim();
+        if !query.is_empty() && !query.starts_with('#') {
+            queries.push(query.to_owned());
+        }
+    }
+    Ok(queries)
+}
+
+/// Loads the list, reporting what was noticed about it unless `quiet`.
+fn load_list(file: Option<&Path>, update: bool, quiet: bool) -> Result<LoadedList> {
+    let ip_list = list::load(file, update)?;
+    if !quiet {
+        if let Some(reason) = &ip_list.fallback_reason {
+            eprintln!("Using the embedded snapshot of the IP list: {}", reason);
+        }
+        for diagnostic in &ip_list.diagnostics {
+            eprintln!("Skipped IP list entry: {:?}", diagnostic);
+        }
+    }
+    Ok(ip_list)
+}
+
+fn run_logs<I: Iterator<Item = String>>(args: I) -> Result<i32> {
+    let options = match logs::parse_args(args)? {
+        Some(options) => options,
+        None => {
+            print!("{}", logs::USAGE);
+            return Ok(EXIT_FASTLY);
+        }
+    };
+
+    let ip_list = load_list(options.list.as_deref(), options.update, options.quiet)?;
+    let summary = logs::run(&options, &ip_list.matcher())?;
+    if !options.quiet {
+        eprintln!(
+            "{} lines: {} Fastly, {} other, {} without a client address",
+            summary.lines, summary.fastly, summary.other, summary.unknown
+        );
+    }
+    Ok(EXIT_FASTLY)
+}
+
+fn run() -> Result<i32> {
+    let mut args = std::env::args().skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("logs") {
+        args.next();
+        return run_logs(args);
+    }
+
+    let options = match parse_args(args)? {
+        Some(options) => options,
+        None => {
+            print!("{}", USAGE);
+            return Ok(EXIT_FASTLY);
+        }
+    };
+
+    let ip_list = load_list(options.list.as_deref(), options.update, options.quiet)?;
+
+    let queries = match options.queries.as_slice() {
+        [] => read_queries()?,
+        [only] if only == "-" => read_queries()?,
+        _ => options.queries,
+    };
+    if queries.is_empty() {
+        bail!("No queries to check");
+    }
+
+    let matcher = ip_list.matcher();
+    let outcomes: Vec<Outcome> = queries
+        .iter()
+        .map(|query| Outcome::check(query, &matcher))
+        .collect();
+    let code = if outcomes.iter().any(Outcome::is_error) {
+        EXIT_ERROR
+    } else if outcomes.iter().all(Outcome::is_fastly) {
+        EXIT_FASTLY
+    } else {
+        EXIT_NOT_FASTLY
+    };
+
+    if !options.quiet {
+        if options.format == Format::Csv
+            && !outcomes
+                .iter()
+                .all(|outcome| outcome.is_same_kind(&outcomes[0]))
+        {
+            bail!("CSV output needs every query to be of one kind: addresses, networks or ranges");
+        }
+        // A single query is answered like the service answers one lookup.
+        let mut output = match outcomes.as_slice() {
+            [Outcome::Addr(BatchItem {
+                result: Some(result),
+                ..
+            })] => result.render(options.format)?,
+            [outcome] => outcome.render(options.format)?,
+            _ => Outcomes(outcomes).render(options.format)?,
+        };
+        if options.format == Format::Json {
+            output.push('\n');
+        }
+        print!("{}", output);
+    }
+    Ok(code)
+}
+
+fn main() {
+    let code = match run() {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("isfastlyip: {:#}", err);
+            EXIT_ERROR
+        }
+    };
+    process::exit(code);
+}
@@ -0,0 +1,111 @@
+use anyhow::Result;
+use isfastlyip::addr::{self, Query};
+use isfastlyip::batch::{self, BatchItem};
+use isfastlyip::coverage::{self, Coverage, NetCheckResult, RangeCheckResult};
+use isfastlyip::render::Render;
+use isfastlyip::IpMatcher;
+
+/// The answer to one query, rendered the way the service renders it.
+#[derive(serde::Serialize, Debug)]
+#[serde(untagged)]
+pub enum Outcome {
+    /// An address, or a query that could not be checked.
+    Addr(BatchItem),
+    Net(NetCheckResult),
+    Range(RangeCheckResult),
+}
+
+impl Outcome {
+    pub fn check(query: &str, matcher: &IpMatcher) -> Self {
+        let error = |err: String| {
+            Outcome::Addr(BatchItem {
+                query: query.to_owned(),
+                result: None,
+                error: Some(err),
+            })
+        };
+        match addr::parse_value(query) {
+            Ok(Query::Addr(_)) => Outcome::Addr(batch::check_item(query, matcher)),
+            Ok(Query::Net(net)) => Outcome::Net(coverage::check_net(net, matcher)),
+            Ok(Query::Range(start, end)) => match coverage::check_range(start, end, matcher) {
+                Ok(result) => Outcome::Range(result),
+                Err(err) => error(err.to_string()),
+            },
+            Err(err) => error(err.to_string()),
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, Outcome::Addr(BatchItem { error: Some(_), .. }))
+    }
+
+    /// Whether every queried address is listed.
+    pub fn is_fastly(&self) -> bool {
+        match self {
+            Outcome::Addr(item) => matches!(&item.result, Some(result) if result.is_fastly_ip),
+            Outcome::Net(result) => result.coverage == Coverage::Covered,
+            Outcome::Range(result) => result.fastly_addresses == result.total_addresses,
+        }
+    }
+
+    /// Outcomes of the same kind share a CSV header.
+    pub fn is_same_kind(&self, other: &Outcome) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+impl Render for Outcome {
+    fn text(&self) -> String {
+        match self {
+            Outcome::Addr(item) => item.text(),
+            Outcome::Net(result) => result.text(),
+            Outcome::Range(result) => result.text(),
+        }
+    }
+
+    fn csv_header(&self) -> &'static [&'static str] {
+        match self {
+            Outcome::Addr(item) => item.csv_header(),
+            Outcome::Net(result) => result.csv_header(),
+            Outcome::Range(result) => result.csv_header(),
+        }
+    }
+
+    fn csv_rows(&self) -> Vec<Vec<String>> {
+        match self {
+            Outcome::Addr(item) => item.csv_rows(),
+            Outcome::Net(result) => result.csv_rows(),
+            Outcome::Range(result) => result.csv_rows(),
+        }
+    }
+}
+
+/// The outcomes of several queries, rendered like a batch.
+#[derive(serde::Serialize, Debug)]
+#[serde(transparent)]
+pub struct Outcomes(pub Vec<Outcome>);
+
+impl Render for Outcomes {
+    fn text(&self) -> String {
+        self.0.iter().map(Outcome::text).collect()
+    }
+
+    fn csv_header(&self) -> &'static [&'static str] {
+        match self.0.first() {
+            Some(outcome) => outcome.csv_header(),
+            None => &[],
+        }
+    }
+
+    fn csv_rows(&self) -> Vec<Vec<String>> {
+        self.0.iter().flat_map(Outcome::csv_rows).collect()
+    }
+
+    fn ndjson(&self) -> Result<String> {
+        let mut ndjson = String::new();
+        for outcome in &self.0 {
+            ndjson.push_str(&outcome.ndjson()?);
+        }
+        Ok(ndjson)
+    }
+}
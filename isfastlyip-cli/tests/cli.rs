@@ -0,0 +1,98 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn fixture() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../isfastlyip/tests/fixtures/public-ip-list")
+}
+
+fn isfastlyip(args: &[&str], stdin: &str) -> Output {
+    run(&[], args, stdin)
+}
+
+fn isfastlyip_logs(args: &[&str], stdin: &str) -> Output {
+    run(&["logs"], args, stdin)
+}
+
+fn run(command: &[&str], args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_isfastlyip"))
+        .args(command)
+        .arg("--list")
+        .arg(fixture())
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> &str {
+    std::str::from_utf8(&output.stdout).unwrap()
+}
+
+#[test]
+fn exit_status() {
+    let output = isfastlyip(&["151.101.230.73"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "true\n");
+
+    let output = isfastlyip(&["151.101.230.73", "8.8.8.8"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "true\nfalse\n");
+
+    let output = isfastlyip(&["-q", "151.101.230.73", "not-an-ip"], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stdout(&output), "");
+}
+
+#[test]
+fn networks_and_ranges() {
+    let output = isfastlyip(&["151.101.64.0/22", "151.101.255.0-151.102.0.255"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "true\nfalse\n");
+
+    let output = isfastlyip(
+        &["--format=csv", "151.101.64.0/22", "151.101.0.0-151.101.0.1"],
+        "",
+    );
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn formats_match_the_service() {
+    let output = isfastlyip(&["-f", "json", "151.101.230.73"], "");
+    assert_eq!(
+        stdout(&output),
+        "{\"is_fastly_ip\":true,\"ip\":\"151.101.230.73\",\"family\":\"ipv4\",\"matched_prefix\":\"151.101.0.0/16\"}\n"
+    );
+
+    let output = isfastlyip(
+        &["--format", "ndjson"],
+        "151.101.230.73\n# comment\n\n8.8.8.8\n",
+    );
+    let lines: Vec<&str> = stdout(&output).lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("{\"query\":\"151.101.230.73\",\"is_fastly_ip\":true"));
+
+    let output = isfastlyip(&["-f", "csv", "-"], "151.101.230.73\n8.8.8.8\n");
+    assert!(stdout(&output).starts_with("query,is_fastly_ip,"));
+    assert_eq!(stdout(&output).lines().count(), 3);
+}
+
+#[test]
+fn annotates_logs() {
+    let output = isfastlyip_logs(
+        &["--only", "other"],
+        "151.101.1.1 - - [10/Oct/2021:13:55:36 -0700] \"GET / HTTP/1.1\" 200 1\n8.8.8.8 - - [-] \"GET /\" 200 1\n",
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "8.8.8.8 - - [-] \"GET /\" 200 1\n");
+}
@@ -0,0 +1,91 @@
+//! An actix-web middleware, registered with `App::wrap`.
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{Error, HttpMessage, HttpResponse};
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+
+use crate::ips::{rejection_body, FastlyIps, Mode};
+
+/// Rejects requests whose peer is not a Fastly address with
+/// `403 Forbidden`, and records the [`IpCheckResult`](crate::IpCheckResult)
+/// of the others as request data, for handlers to take as
+/// `web::ReqData<IpCheckResult>`.
+#[derive(Clone)]
+pub struct FastlyOnly {
+    ips: FastlyIps,
+    mode: Mode,
+}
+
+impl FastlyOnly {
+    pub fn new(ips: FastlyIps) -> Self {
+        FastlyOnly {
+            ips,
+            mode: Mode::Enforce,
+        }
+    }
+
+    /// Lets every request through, only recording its verdict.
+    pub fn report_only(mut self) -> Self {
+        self.mode = Mode::ReportOnly;
+        self
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for FastlyOnly
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = FastlyOnlyMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(FastlyOnlyMiddleware {
+            service,
+            config: self.clone(),
+        }))
+    }
+}
+
+/// The service [`FastlyOnly`] wraps others in.
+pub struct FastlyOnlyMiddleware<S> {
+    service: S,
+    config: FastlyOnly,
+}
+
+impl<S, B> Service<ServiceRequest> for FastlyOnlyMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let peer = req.peer_addr().map(|addr| addr.ip());
+        let decision = self.config.ips.decide(peer, self.config.mode);
+        if !decision.allowed {
+            let resp = HttpResponse::Forbidden()
+                .content_type("text/plain; charset=utf-8")
+                .body(rejection_body(peer));
+            return Box::pin(ready(Ok(req.into_response(resp).map_into_right_body())));
+        }
+
+        if let Some(result) = decision.result {
+            req.extensions_mut().insert(result);
+        }
+        let future = self.service.call(req);
+        Box::pin(async move { Ok(future.await?.map_into_left_body()) })
+    }
+}
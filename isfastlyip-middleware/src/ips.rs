@@ -0,0 +1,131 @@
+use anyhow::{Context, Result};
+use isfastlyip::{service, snapshot, validate, IpCheckResult, IpMatcher, IpRanges};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+/// What happens to requests that do not come from Fastly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Reject them with `403 Forbidden`.
+    Enforce,
+    /// Let them through with their verdict, to find out what would be
+    /// rejected before enforcing.
+    ReportOnly,
+}
+
+/// The Fastly networks, shared by every copy of the middleware and replaced
+/// as a whole when the list is refreshed.
+#[derive(Clone)]
+pub struct FastlyIps {
+    matcher: Arc<RwLock<Arc<IpMatcher>>>,
+}
+
+/// Reads a `public-ip-list` body, skipping entries that are not networks.
+pub(crate) fn parse_list(body: &[u8]) -> Result<IpRanges> {
+    Ok(validate::validate_response(200, None, body)?.ranges)
+}
+
+impl FastlyIps {
+    pub fn new(ranges: IpRanges) -> Self {
+        FastlyIps {
+            matcher: Arc::new(RwLock::new(Arc::new(ranges.matcher()))),
+        }
+    }
+
+    /// The snapshot of the list compiled into `isfastlyip`, to answer from
+    /// until the first refresh.
+    pub fn embedded() -> Self {
+        FastlyIps::new(snapshot::ranges())
+    }
+
+    pub fn from_json(body: &[u8]) -> Result<Self> {
+        Ok(FastlyIps::new(parse_list(body)?))
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let body =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        FastlyIps::from_json(&body)
+    }
+
+    /// Swaps in a new list. Requests being checked finish against the old
+    /// one.
+    pub fn replace(&self, ranges: IpRanges) {
+        let matcher = Arc::new(ranges.matcher());
+        *self.matcher.write().unwrap_or_else(|err| err.into_inner()) = matcher;
+    }
+
+    pub fn check(&self, addr: IpAddr) -> IpCheckResult {
+        let matcher = self
+            .matcher
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone();
+        service::check_ip(addr, &matcher)
+    }
+
+    /// Decides about a request from its peer address. A request without one
+    /// cannot be told to come from Fastly and is rejected when enforcing.
+    #[cfg(any(feature = "tower", feature = "actix"))]
+    pub(crate) fn decide(&self, peer: Option<IpAddr>, mode: Mode) -> Decision {
+        let result = peer.map(|peer| self.check(peer));
+        let is_fastly_ip = matches!(&result, Some(result) if result.is_fastly_ip);
+        Decision {
+            allowed: is_fastly_ip || mode == Mode::ReportOnly,
+            result,
+        }
+    }
+}
+
+#[cfg(any(feature = "tower", feature = "actix"))]
+pub(crate) struct Decision {
+    pub allowed: bool,
+    /// The verdict kept as a request extension.
+    pub result: Option<IpCheckResult>,
+}
+
+/// The body of a rejection.
+#[cfg(any(feature = "tower", feature = "actix"))]
+pub(crate) fn rejection_body(peer: Option<IpAddr>) -> String {
+    match peer {
+        Some(peer) => format!("{} is not a Fastly address\n", peer),
+        None => "The peer address of the request is unknown\n".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIST: &[u8] = br#"{"addresses": ["151.101.0.0/16"], "ipv6_addresses": []}"#;
+
+    #[test]
+    fn replace() {
+        let ips = FastlyIps::from_json(LIST).unwrap();
+        assert!(ips.check("151.101.1.1".parse().unwrap()).is_fastly_ip);
+
+        let mut ranges = IpRanges::default();
+        ranges.push("8.8.8.0/24".parse().unwrap());
+        ips.clone().replace(ranges);
+        assert!(ips.check("8.8.8.8".parse().unwrap()).is_fastly_ip);
+        assert!(!ips.check("151.101.1.1".parse().unwrap()).is_fastly_ip);
+    }
+
+    #[cfg(any(feature = "tower", feature = "actix"))]
+    #[test]
+    fn decisions() {
+        let ips = FastlyIps::from_json(LIST).unwrap();
+        let fastly = "151.101.1.1".parse().ok();
+        let other = "8.8.8.8".parse().ok();
+
+        assert!(ips.decide(fastly, Mode::Enforce).allowed);
+        assert!(!ips.decide(other, Mode::Enforce).allowed);
+        assert!(!ips.decide(None, Mode::Enforce).allowed);
+
+        let decision = ips.decide(other, Mode::ReportOnly);
+        assert!(decision.allowed);
+        assert!(!decision.result.unwrap().is_fastly_ip);
+    }
+}
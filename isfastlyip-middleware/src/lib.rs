@@ -0,0 +1,23 @@
+//! Middleware locking an origin to Fastly: requests whose peer address is
+//! not one of Fastly's are rejected, and the verdict on the others is kept
+//! as a request extension.
+//!
+//! [`FastlyIps`] holds the list, which a [`refresh::Refresher`] can keep
+//! current from `public-ip-list` or a local file. The `tower` feature
+//! provides [`tower::FastlyOnlyLayer`], with axum's `ConnectInfo` understood
+//! under the `axum` feature, and the `actix` feature provides
+//! [`actix::FastlyOnly`].
+//!
+//! Only the address of the connection is checked. Headers such as
+//! `Fastly-Client-IP` or `X-Forwarded-For` are set by whoever sends the
+//! request and prove nothing about it coming through Fastly.
+
+#[cfg(feature = "actix")]
+pub mod actix;
+mod ips;
+pub mod refresh;
+#[cfg(feature = "tower")]
+pub mod tower;
+
+pub use ips::{FastlyIps, Mode};
+pub use isfastlyip::IpCheckResult;
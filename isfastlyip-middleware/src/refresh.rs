@@ -0,0 +1,97 @@
+//! Keeping [`FastlyIps`] current from the published list or a local file.
+
+use anyhow::{Context, Result};
+use isfastlyip::IpRanges;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::ips::{parse_list, FastlyIps};
+
+/// Where Fastly publishes its list.
+pub const PUBLIC_IP_LIST_URL: &str = "https://api.fastly.com/public-ip-list";
+
+/// Where the list is refreshed from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    /// A URL serving the `public-ip-list` JSON.
+    #[cfg(feature = "fetch")]
+    Url(String),
+    /// A local copy of the `public-ip-list` JSON.
+    File(PathBuf),
+}
+
+impl Source {
+    /// The list published by Fastly.
+    #[cfg(feature = "fetch")]
+    pub fn public_ip_list() -> Self {
+        Source::Url(PUBLIC_IP_LIST_URL.to_owned())
+    }
+
+    pub fn load(&self) -> Result<IpRanges> {
+        match self {
+            #[cfg(feature = "fetch")]
+            Source::Url(url) => fetch(url),
+            Source::File(path) => {
+                let body = std::fs::read(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                parse_list(&body)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "fetch")]
+fn fetch(url: &str) -> Result<IpRanges> {
+    use std::io::Read;
+
+    let resp = ureq::get(url)
+        .timeout(Duration::from_secs(30))
+        .call()
+        .with_context(|| format!("Failed to fetch {}", url))?;
+    let status = resp.status();
+    let content_type = resp.header("content-type").map(str::to_owned);
+    let mut body = Vec::new();
+    resp.into_reader().take(16 << 20).read_to_end(&mut body)?;
+    Ok(isfastlyip::validate::validate_response(status, content_type.as_deref(), &body)?.ranges)
+}
+
+/// Reloads the list once. The current list is kept when the source fails.
+pub fn refresh(ips: &FastlyIps, source: &Source) -> Result<()> {
+    ips.replace(source.load()?);
+    Ok(())
+}
+
+/// Refreshes a list in the background until it is stopped or dropped.
+#[derive(Debug)]
+pub struct Refresher {
+    /// Dropping the sender wakes the thread up to exit.
+    stop: Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl Refresher {
+    /// Refreshes `ips` from `source` every `interval` on its own thread,
+    /// logging failures to stderr. The first refresh happens right away.
+    pub fn spawn(ips: FastlyIps, source: Source, interval: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn(move || loop {
+            if let Err(err) = refresh(&ips, &source) {
+                eprintln!("Failed to refresh the Fastly IP list: {:#}", err);
+            }
+            match stopped.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => continue,
+                _ => return,
+            }
+        });
+        Refresher { stop, thread }
+    }
+
+    /// Stops refreshing, waiting for a refresh in progress to finish.
+    /// Dropping the refresher stops it without waiting.
+    pub fn stop(self) {
+        drop(self.stop);
+        let _ = self.thread.join();
+    }
+}
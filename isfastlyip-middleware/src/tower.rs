@@ -0,0 +1,143 @@
+//! A tower [`Layer`] for axum, hyper and other tower-based servers.
+
+use http::{header, Extensions, Request, Response, StatusCode};
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::ips::{rejection_body, FastlyIps, Mode};
+
+type PeerAddr = Arc<dyn Fn(&Extensions) -> Option<IpAddr> + Send + Sync>;
+
+/// Finds the peer address axum records with `into_make_service_with_connect_info`,
+/// or a bare [`SocketAddr`] extension.
+fn default_peer_addr(extensions: &Extensions) -> Option<IpAddr> {
+    #[cfg(feature = "axum")]
+    {
+        use axum::extract::ConnectInfo;
+        if let Some(ConnectInfo(addr)) = extensions.get::<ConnectInfo<SocketAddr>>() {
+            return Some(addr.ip());
+        }
+    }
+    extensions.get::<SocketAddr>().map(SocketAddr::ip)
+}
+
+/// Wraps services in [`FastlyOnly`].
+#[derive(Clone)]
+pub struct FastlyOnlyLayer {
+    ips: FastlyIps,
+    mode: Mode,
+    peer_addr: PeerAddr,
+}
+
+impl FastlyOnlyLayer {
+    pub fn new(ips: FastlyIps) -> Self {
+        FastlyOnlyLayer {
+            ips,
+            mode: Mode::Enforce,
+            peer_addr: Arc::new(default_peer_addr),
+        }
+    }
+
+    /// Lets every request through, only recording its verdict.
+    pub fn report_only(mut self) -> Self {
+        self.mode = Mode::ReportOnly;
+        self
+    }
+
+    /// Reads the peer address from the request extensions some other way
+    /// than the default, for servers that record it under their own type.
+    pub fn peer_addr<F>(mut self, peer_addr: F) -> Self
+    where
+        F: Fn(&Extensions) -> Option<IpAddr> + Send + Sync + 'static,
+    {
+        self.peer_addr = Arc::new(peer_addr);
+        self
+    }
+}
+
+impl<S> Layer<S> for FastlyOnlyLayer {
+    type Service = FastlyOnly<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        FastlyOnly {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Rejects requests whose peer is not a Fastly address with
+/// `403 Forbidden`, and records the [`IpCheckResult`](crate::IpCheckResult)
+/// of the others as a request extension.
+#[derive(Clone)]
+pub struct FastlyOnly<S> {
+    inner: S,
+    layer: FastlyOnlyLayer,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for FastlyOnly<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    ResBody: From<String>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future, ResBody>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        let peer = (self.layer.peer_addr)(req.extensions());
+        let decision = self.layer.ips.decide(peer, self.layer.mode);
+        if !decision.allowed {
+            let mut resp = Response::new(ResBody::from(rejection_body(peer)));
+            *resp.status_mut() = StatusCode::FORBIDDEN;
+            resp.headers_mut().insert(
+                header::CONTENT_TYPE,
+                header::HeaderValue::from_static("text/plain; charset=utf-8"),
+            );
+            return ResponseFuture::Rejected {
+                response: Some(resp),
+            };
+        }
+
+        if let Some(result) = decision.result {
+            req.extensions_mut().insert(result);
+        }
+        ResponseFuture::Inner {
+            future: self.inner.call(req),
+        }
+    }
+}
+
+pin_project_lite::pin_project! {
+    /// The response of [`FastlyOnly`].
+    #[project = ResponseFutureProj]
+    pub enum ResponseFuture<F, B> {
+        Inner { #[pin] future: F },
+        Rejected { response: Option<Response<B>> },
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F, B>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            ResponseFutureProj::Inner { future } => future.poll(cx),
+            ResponseFutureProj::Rejected { response } => {
+                Poll::Ready(Ok(response.take().expect("polled after completion")))
+            }
+        }
+    }
+}
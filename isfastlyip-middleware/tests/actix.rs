@@ -0,0 +1,60 @@
+#![cfg(feature = "actix")]
+
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::{test, web, App};
+use isfastlyip_middleware::actix::FastlyOnly;
+use isfastlyip_middleware::{FastlyIps, IpCheckResult};
+
+async fn verdict(verdict: web::ReqData<IpCheckResult>) -> String {
+    verdict
+        .matched_prefix
+        .map(|net| net.to_string())
+        .unwrap_or_default()
+}
+
+#[actix_web::test]
+async fn rejects_other_peers() {
+    let ips = FastlyIps::from_file(common::fixture()).unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(FastlyOnly::new(ips))
+            .route("/", web::get().to(verdict)),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .peer_addr("151.101.1.1:443".parse().unwrap())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(test::read_body(resp).await, "151.101.0.0/16");
+
+    let req = test::TestRequest::get()
+        .peer_addr("8.8.8.8:443".parse().unwrap())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    assert_eq!(
+        test::read_body(resp).await,
+        "8.8.8.8 is not a Fastly address\n"
+    );
+}
+
+#[actix_web::test]
+async fn report_only() {
+    let ips = FastlyIps::from_file(common::fixture()).unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(FastlyOnly::new(ips).report_only())
+            .route("/", web::get().to(verdict)),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .peer_addr("8.8.8.8:443".parse().unwrap())
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
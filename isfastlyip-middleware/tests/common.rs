@@ -0,0 +1,6 @@
+use std::path::PathBuf;
+
+/// The list the `isfastlyip` tests are run against.
+pub fn fixture() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../isfastlyip/tests/fixtures/public-ip-list")
+}
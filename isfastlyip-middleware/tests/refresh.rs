@@ -0,0 +1,60 @@
+mod common;
+
+use isfastlyip_middleware::refresh::{self, Source};
+use isfastlyip_middleware::FastlyIps;
+
+#[test]
+fn refresh_from_file() {
+    let ips =
+        FastlyIps::from_json(br#"{"addresses": ["8.8.8.0/24"], "ipv6_addresses": []}"#).unwrap();
+    assert!(ips.check("8.8.8.8".parse().unwrap()).is_fastly_ip);
+
+    refresh::refresh(&ips, &Source::File(common::fixture())).unwrap();
+    assert!(!ips.check("8.8.8.8".parse().unwrap()).is_fastly_ip);
+    assert!(ips.check("151.101.1.1".parse().unwrap()).is_fastly_ip);
+
+    // A broken source keeps the current list.
+    assert!(refresh::refresh(&ips, &Source::File("missing.json".into())).is_err());
+    assert!(ips.check("151.101.1.1".parse().unwrap()).is_fastly_ip);
+}
+
+/// Serves the fixture list from a local server and refreshes from it in the
+/// background.
+#[cfg(feature = "fetch")]
+#[test]
+fn refresh_from_url() {
+    use isfastlyip_middleware::refresh::Refresher;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/public-ip-list", listener.local_addr().unwrap());
+    let body = std::fs::read(common::fixture()).unwrap();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(&body).unwrap();
+        }
+    });
+
+    let ips =
+        FastlyIps::from_json(br#"{"addresses": ["8.8.8.0/24"], "ipv6_addresses": []}"#).unwrap();
+    let refresher = Refresher::spawn(ips.clone(), Source::Url(url), Duration::from_secs(3600));
+    for _ in 0..100 {
+        if ips.check("151.101.1.1".parse().unwrap()).is_fastly_ip {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    refresher.stop();
+    assert!(ips.check("151.101.1.1".parse().unwrap()).is_fastly_ip);
+}
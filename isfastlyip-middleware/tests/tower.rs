@@ -0,0 +1,97 @@
+#![cfg(feature = "axum")]
+
+mod common;
+
+use axum::body::{to_bytes, Body};
+use axum::extract::ConnectInfo;
+use axum::http::{Request, StatusCode};
+use axum::routing::get;
+use axum::{Extension, Router};
+use isfastlyip::IpRanges;
+use isfastlyip_middleware::tower::FastlyOnlyLayer;
+use isfastlyip_middleware::{FastlyIps, IpCheckResult};
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tower::ServiceExt;
+
+fn app(layer: FastlyOnlyLayer) -> Router {
+    Router::new()
+        .route(
+            "/",
+            get(|Extension(verdict): Extension<IpCheckResult>| async move {
+                verdict.is_fastly_ip.to_string()
+            }),
+        )
+        .layer(layer)
+}
+
+async fn request(app: Router, peer: Option<&str>) -> (StatusCode, String) {
+    let mut req = Request::get("/").body(Body::empty()).unwrap();
+    if let Some(peer) = peer {
+        let addr: SocketAddr = peer.parse().unwrap();
+        req.extensions_mut().insert(ConnectInfo(addr));
+    }
+    let resp = app.oneshot(req).await.unwrap();
+    let status = resp.status();
+    let body = to_bytes(resp.into_body(), 1 << 16).await.unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn rejects_other_peers() {
+    let layer = FastlyOnlyLayer::new(FastlyIps::from_file(common::fixture()).unwrap());
+
+    assert_eq!(
+        request(app(layer.clone()), Some("151.101.1.1:443")).await,
+        (StatusCode::OK, "true".to_owned())
+    );
+    assert_eq!(
+        request(app(layer.clone()), Some("8.8.8.8:443")).await,
+        (
+            StatusCode::FORBIDDEN,
+            "8.8.8.8 is not a Fastly address\n".to_owned()
+        )
+    );
+    assert_eq!(request(app(layer), None).await.0, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn report_only() {
+    let layer =
+        FastlyOnlyLayer::new(FastlyIps::from_file(common::fixture()).unwrap()).report_only();
+    assert_eq!(
+        request(app(layer), Some("[2001:db8::1]:443")).await,
+        (StatusCode::OK, "false".to_owned())
+    );
+}
+
+/// Serves the app on a local port and requests it over loopback, which is
+/// only let through once the list says loopback is Fastly's.
+#[tokio::test]
+async fn local_server() {
+    let ips = FastlyIps::from_file(common::fixture()).unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let service =
+        app(FastlyOnlyLayer::new(ips.clone())).into_make_service_with_connect_info::<SocketAddr>();
+    tokio::spawn(async move { axum::serve(listener, service).await.unwrap() });
+
+    let status = |response: String| response.split(' ').nth(1).unwrap_or_default().to_owned();
+    assert_eq!(status(get_once(addr).await), "403");
+
+    let mut loopback = IpRanges::default();
+    loopback.push("127.0.0.0/8".parse().unwrap());
+    ips.replace(loopback);
+    assert_eq!(status(get_once(addr).await), "200");
+}
+
+async fn get_once(addr: SocketAddr) -> String {
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
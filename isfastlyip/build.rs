@@ -0,0 +1,41 @@
+//! Embeds the newest `data/public-ip-list-<date>.json` snapshot, so the
+//! service can still answer when the Fastly API is unreachable.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+const PREFIX: &str = "public-ip-list-";
+const SUFFIX: &str = ".json";
+
+fn main() {
+    let data_dir = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("data");
+    println!("cargo:rerun-if-changed={}", data_dir.display());
+
+    let newest = fs::read_dir(&data_dir)
+        .expect("data directory is missing")
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with(PREFIX) && name.ends_with(SUFFIX))
+        .max()
+        .expect("no public-ip-list snapshot in the data directory");
+    let date = &newest[PREFIX.len()..newest.len() - SUFFIX.len()];
+    let path = data_dir.join(&newest);
+
+    let body = fs::read_to_string(&path).unwrap();
+    let list: serde_json::Value = serde_json::from_str(&body)
+        .unwrap_or_else(|err| panic!("{} is not valid JSON: {}", newest, err));
+    for key in &["addresses", "ipv6_addresses"] {
+        assert!(list[key].is_array(), "{} has no {} array", newest, key);
+    }
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("snapshot.rs");
+    fs::write(
+        out,
+        format!(
+            "pub const SNAPSHOT_DATE: &str = {:?};\npub const SNAPSHOT_JSON: &str = include_str!({:?});\n",
+            date,
+            path.display().to_string()
+        ),
+    )
+    .unwrap();
+}
@@ -0,0 +1,82 @@
+//! A webhook backend for local testing. It verifies the signature of every
+//! change notification it receives and prints the payload.
+//!
+//!     WEBHOOK_SECRET=local-test-secret cargo run -p isfastlyip --example webhook_receiver
+//!
+//! Set `WEBHOOK_STATUS` to answer with another status, such as 503 to watch
+//! deliveries being retried by later refreshes.
+
+use isfastlyip::webhook::{self, SIGNATURE_HEADER};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const ADDR: &str = "127.0.0.1:8001";
+const TOLERANCE: i64 = 5 * 60;
+
+fn handle(stream: TcpStream, secret: Option<&str>, status: u16) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let mut content_length = 0;
+    let mut signature = None;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(colon) = line.find(':') {
+            let (name, value) = (line[..colon].trim(), line[colon + 1..].trim());
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse().unwrap_or(0);
+            } else if name.eq_ignore_ascii_case(SIGNATURE_HEADER) {
+                signature = Some(value.to_owned());
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    println!("{}", request_line.trim_end());
+    match (secret, signature) {
+        (Some(secret), Some(signature)) => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_secs() as i64)
+                .unwrap_or(0);
+            match webhook::verify(secret, &signature, &body, now, TOLERANCE) {
+                Ok(()) => println!("Signature verified"),
+                Err(err) => println!("Signature rejected: {}", err),
+            }
+        }
+        (Some(_), None) => println!("Unsigned delivery"),
+        (None, _) => println!("WEBHOOK_SECRET is not set, not verifying the signature"),
+    }
+    println!("{}\n", String::from_utf8_lossy(&body));
+
+    write!(
+        &stream,
+        "HTTP/1.1 {} Webhook\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status
+    )
+}
+
+fn main() -> std::io::Result<()> {
+    let secret = std::env::var("WEBHOOK_SECRET").ok();
+    let status = std::env::var("WEBHOOK_STATUS")
+        .ok()
+        .and_then(|status| status.parse().ok())
+        .unwrap_or(204);
+
+    let listener = TcpListener::bind(ADDR)?;
+    println!("Listening for webhooks on http://{}", ADDR);
+    for stream in listener.incoming() {
+        if let Err(err) = handle(stream?, secret.as_deref(), status) {
+            eprintln!("Failed to handle a delivery: {}", err);
+        }
+    }
+    Ok(())
+}
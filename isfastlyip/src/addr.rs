@@ -0,0 +1,362 @@
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use std::fmt;
+use std::net::IpAddr;
+
+/// Why a string could not be read as an address, network or range.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AddrError {
+    Empty,
+    InvalidPercentEncoding,
+    UnbalancedBrackets,
+    BracketedIpv4,
+    InvalidZone(String),
+    ZoneOnIpv4,
+    LeadingZero(String),
+    InvalidPrefixLength(String),
+    InvalidAddress(String),
+}
+
+impl fmt::Display for AddrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddrError::Empty => write!(f, "No IP address given"),
+            AddrError::InvalidPercentEncoding => {
+                write!(f, "Path is not valid percent-encoded UTF-8")
+            }
+            AddrError::UnbalancedBrackets => write!(f, "Unbalanced brackets around IPv6 address"),
+            AddrError::BracketedIpv4 => {
+                write!(f, "Brackets are only allowed around IPv6 addresses")
+            }
+            AddrError::InvalidZone(zone) => write!(f, "Invalid zone ID {:?}", zone),
+            AddrError::ZoneOnIpv4 => write!(f, "Zone IDs are only allowed on IPv6 addresses"),
+            AddrError::LeadingZero(octet) => write!(
+                f,
+                "IPv4 octet {:?} has a leading zero, which is ambiguous",
+                octet
+            ),
+            AddrError::InvalidPrefixLength(len) => write!(f, "Invalid prefix length {:?}", len),
+            AddrError::InvalidAddress(addr) => {
+                write!(f, "{:?} is not a valid IPv4 or IPv6 address", addr)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AddrError {}
+
+/// What a lookup path asks about.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Query {
+    /// A single address, such as `151.101.230.73`.
+    Addr(IpAddr),
+    /// A network in CIDR notation, such as `151.101.64.0/22`.
+    Net(IpNet),
+    /// An inclusive range, such as `151.101.0.0-151.101.3.255`.
+    Range(IpAddr, IpAddr),
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|digit| digit as u8)
+}
+
+/// Decodes `%XX` escapes. A `%` not followed by two hex digits is kept as
+/// is, so that unescaped zone IDs such as `fe80::1%eth0` still come through.
+pub(crate) fn percent_decode(input: &str) -> Result<String, AddrError> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            if let (Some(high), Some(low)) = (
+                bytes.get(index + 1).cloned().and_then(hex_value),
+                bytes.get(index + 2).cloned().and_then(hex_value),
+            ) {
+                decoded.push(high << 4 | low);
+                index += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[index]);
+        index += 1;
+    }
+    String::from_utf8(decoded).map_err(|_| AddrError::InvalidPercentEncoding)
+}
+
+/// Finds the last value of `name` in a query string, percent-decoded.
+pub fn query_param(query: Option<&str>, name: &str) -> Option<String> {
+    query
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) if key == name => percent_decode(value).ok(),
+                _ => None,
+            }
+        })
+        .next_back()
+}
+
+/// Rejects dotted-quad octets with leading zeros, which some parsers read
+/// as octal and others as decimal.
+fn check_leading_zeros(addr: &str) -> Result<(), AddrError> {
+    let dotted = addr.rsplit(':').next().unwrap_or(addr);
+    if !dotted.contains('.') {
+        return Ok(());
+    }
+    for octet in dotted.split('.') {
+        if octet.len() > 1 && octet.starts_with('0') {
+            return Err(AddrError::LeadingZero(octet.to_owned()));
+        }
+    }
+    Ok(())
+}
+
+/// Parses one address, optionally in brackets and with a zone ID.
+///
+/// The zone ID is validated and then dropped, since it has no bearing on
+/// which network the address belongs to.
+pub fn parse_addr(input: &str) -> Result<IpAddr, AddrError> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err(AddrError::Empty);
+    }
+
+    let bracketed = input.starts_with('[');
+    if bracketed != input.ends_with(']') {
+        return Err(AddrError::UnbalancedBrackets);
+    }
+    let unbracketed = if bracketed {
+        &input[1..input.len() - 1]
+    } else {
+        input
+    };
+    if unbracketed.contains(&['[', ']'][..]) {
+        return Err(AddrError::UnbalancedBrackets);
+    }
+
+    let mut parts = unbracketed.splitn(2, '%');
+    let addr = parts.next().unwrap_or_default();
+    let zone = parts.next();
+    if let Some(zone) = zone {
+        let valid = !zone.is_empty() && zone.chars().all(is_zone_char);
+        if !valid {
+            return Err(AddrError::InvalidZone(zone.to_owned()));
+        }
+    }
+
+    check_leading_zeros(addr)?;
+    let parsed = addr
+        .parse::<IpAddr>()
+        .map_err(|_| AddrError::InvalidAddress(addr.to_owned()))?;
+
+    if parsed.is_ipv4() {
+        if bracketed {
+            return Err(AddrError::BracketedIpv4);
+        }
+        if zone.is_some() {
+            return Err(AddrError::ZoneOnIpv4);
+        }
+    }
+    Ok(parsed)
+}
+
+/// Decodes a request path and strips its leading and trailing slash.
+fn path_value(path: &str) -> Result<String, AddrError> {
+    let decoded = percent_decode(path)?;
+    let value = decoded.strip_prefix('/').unwrap_or(&decoded);
+    let value = value.strip_suffix('/').unwrap_or(value);
+    if value.is_empty() {
+        return Err(AddrError::Empty);
+    }
+    Ok(value.to_owned())
+}
+
+/// Extracts the address from a request path such as `/151.101.230.73`.
+pub fn parse_path(path: &str) -> Result<IpAddr, AddrError> {
+    parse_addr(&path_value(path)?)
+}
+
+/// Reads a lookup path as an address, a CIDR network or an address range.
+pub fn parse_query(path: &str) -> Result<Query, AddrError> {
+    parse_value(&path_value(path)?)
+}
+
+fn is_zone_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "-._~".contains(c)
+}
+
+/// Finds the dash separating the ends of a range. Zone IDs may contain
+/// dashes too, so a dash inside one, as in `fe80::1%my-if`, only separates
+/// when what follows it is an address.
+fn range_separator(value: &str) -> Option<usize> {
+    let mut in_zone = false;
+    for (index, c) in value.char_indices() {
+        match c {
+            '%' => in_zone = true,
+            '-' if !in_zone || parse_addr(&value[index + 1..]).is_ok() => return Some(index),
+            c if !is_zone_char(c) => in_zone = false,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Reads an address, a CIDR network or an address range given as is, such
+/// as on a command line.
+pub fn parse_value(value: &str) -> Result<Query, AddrError> {
+    if value.is_empty() {
+        return Err(AddrError::Empty);
+    }
+
+    if let Some(dash) = range_separator(value) {
+        let start = parse_addr(&value[..dash])?;
+        let end = parse_addr(&value[dash + 1..])?;
+        return Ok(Query::Range(start, end));
+    }
+
+    if let Some(slash) = value.rfind('/') {
+        let addr = parse_addr(&value[..slash])?;
+        let len = &value[slash + 1..];
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match len.parse::<u8>() {
+            Ok(prefix_len)
+                if prefix_len <= max_len
+                    && !len.starts_with('+')
+                    && !(len.len() > 1 && len.starts_with('0')) =>
+            {
+                prefix_len
+            }
+            _ => return Err(AddrError::InvalidPrefixLength(len.to_owned())),
+        };
+        let net = match addr {
+            IpAddr::V4(addr) => Ipv4Net::new(addr, prefix_len).map(IpNet::V4),
+            IpAddr::V6(addr) => Ipv6Net::new(addr, prefix_len).map(IpNet::V6),
+        };
+        let net = net.map_err(|_| AddrError::InvalidPrefixLength(len.to_owned()))?;
+        return Ok(Query::Net(net));
+    }
+
+    Ok(Query::Addr(parse_addr(value)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(input: &str) -> IpAddr {
+        input.parse().unwrap()
+    }
+
+    #[test]
+    fn path_with_address() {
+        assert_eq!(parse_path("/151.101.230.73"), Ok(addr("151.101.230.73")));
+        assert_eq!(parse_path("/151.101.230.73/"), Ok(addr("151.101.230.73")));
+        assert_eq!(
+            parse_path("/2A04:4E40:7F6:9100:F8C9:7BF6:E4F5:C5F3"),
+            Ok(addr("2a04:4e40:7f6:9100:f8c9:7bf6:e4f5:c5f3"))
+        );
+        assert_eq!(
+            parse_path("/::ffff:151.101.1.1"),
+            Ok(addr("::ffff:151.101.1.1"))
+        );
+        assert_eq!(parse_path("/%5B2a04:4e40::1%5D"), Ok(addr("2a04:4e40::1")));
+        assert_eq!(parse_path("/fe80::1%25eth0"), Ok(addr("fe80::1")));
+        assert_eq!(parse_path("/[fe80::1%eth0]"), Ok(addr("fe80::1")));
+    }
+
+    #[test]
+    fn path_without_address() {
+        assert_eq!(parse_path("/"), Err(AddrError::Empty));
+        assert_eq!(
+            parse_path("/1a2b3c4"),
+            Err(AddrError::InvalidAddress("1a2b3c4".to_owned()))
+        );
+        assert_eq!(
+            parse_path("/:::1"),
+            Err(AddrError::InvalidAddress(":::1".to_owned()))
+        );
+        assert_eq!(
+            parse_path("/151.101.010.1"),
+            Err(AddrError::LeadingZero("010".to_owned()))
+        );
+        assert_eq!(parse_path("/[151.101.1.1]"), Err(AddrError::BracketedIpv4));
+        assert_eq!(parse_path("/[2a04::1"), Err(AddrError::UnbalancedBrackets));
+        assert_eq!(parse_path("/151.101.1.1%eth0"), Err(AddrError::ZoneOnIpv4));
+        assert_eq!(
+            parse_path("/fe80::1%"),
+            Err(AddrError::InvalidZone("".to_owned()))
+        );
+        assert_eq!(parse_path("/%ff"), Err(AddrError::InvalidPercentEncoding));
+    }
+
+    #[test]
+    fn query_forms() {
+        assert_eq!(
+            parse_query("/151.101.230.73"),
+            Ok(Query::Addr(addr("151.101.230.73")))
+        );
+        assert_eq!(
+            parse_query("/151.101.64.0/22"),
+            Ok(Query::Net("151.101.64.0/22".parse().unwrap()))
+        );
+        assert_eq!(
+            parse_query("/2a04:4e42::/48/"),
+            Ok(Query::Net("2a04:4e42::/48".parse().unwrap()))
+        );
+        assert_eq!(
+            parse_query("/151.101.0.0-151.101.3.255"),
+            Ok(Query::Range(addr("151.101.0.0"), addr("151.101.3.255")))
+        );
+        assert_eq!(
+            parse_query("/151.101.64.0/33"),
+            Err(AddrError::InvalidPrefixLength("33".to_owned()))
+        );
+        assert_eq!(parse_query("/151.101.0.0-"), Err(AddrError::Empty));
+        assert_eq!(
+            parse_query("/151.101.64.0/08"),
+            Err(AddrError::InvalidPrefixLength("08".to_owned()))
+        );
+        assert_eq!(
+            parse_query("/0.0.0.0/0"),
+            Ok(Query::Net("0.0.0.0/0".parse().unwrap()))
+        );
+        assert_eq!(
+            parse_value("2a04:4e42::/48"),
+            Ok(Query::Net("2a04:4e42::/48".parse().unwrap()))
+        );
+        assert_eq!(parse_value(""), Err(AddrError::Empty));
+    }
+
+    #[test]
+    fn zones_with_dashes() {
+        assert_eq!(
+            parse_query("/fe80::1%25my-if"),
+            Ok(Query::Addr(addr("fe80::1")))
+        );
+        assert_eq!(
+            parse_query("/fe80::1%25my-if-fe80::2%25eth0"),
+            Ok(Query::Range(addr("fe80::1"), addr("fe80::2")))
+        );
+        assert_eq!(
+            parse_value("[fe80::1%my-if]-[fe80::2]"),
+            Ok(Query::Range(addr("fe80::1"), addr("fe80::2")))
+        );
+        assert_eq!(
+            parse_value("fe80::%my-if/64"),
+            Ok(Query::Net("fe80::/64".parse().unwrap()))
+        );
+    }
+
+    #[test]
+    fn query_params() {
+        let query = Some("format=csv&at=2021-03-19T12%3A00%3A00%2B02:00&format=text");
+        assert_eq!(query_param(query, "format").as_deref(), Some("text"));
+        assert_eq!(
+            query_param(query, "at").as_deref(),
+            Some("2021-03-19T12:00:00+02:00")
+        );
+        assert_eq!(query_param(query, "for"), None);
+        assert_eq!(query_param(None, "at"), None);
+    }
+}
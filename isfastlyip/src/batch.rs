@@ -0,0 +1,178 @@
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::io::Read;
+
+use crate::addr;
+use crate::matcher::IpMatcher;
+use crate::response::IpCheckResult;
+use crate::service;
+
+/// Upper bound on the number of addresses accepted in one batch request.
+pub const MAX_BATCH_SIZE: usize = 10_000;
+
+/// Upper bound on the size of a batch request body, in bytes: room for
+/// `MAX_BATCH_SIZE` IPv6 addresses in a JSON array, with some to spare.
+pub const MAX_BODY_SIZE: usize = 1 << 20;
+
+/// Why a batch request body was rejected before being parsed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BodyError {
+    TooLarge,
+    NotUtf8,
+    Read(String),
+}
+
+impl BodyError {
+    /// The status of the response rejecting the body.
+    pub fn status(&self) -> u16 {
+        match self {
+            BodyError::TooLarge => 413,
+            BodyError::NotUtf8 | BodyError::Read(_) => 400,
+        }
+    }
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BodyError::TooLarge => write!(f, "Batch body exceeds {} bytes", MAX_BODY_SIZE),
+            BodyError::NotUtf8 => write!(f, "Batch body is not valid UTF-8"),
+            BodyError::Read(err) => write!(f, "Failed to read the batch body: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for BodyError {}
+
+/// Reads a batch request body, giving up as soon as it grows past
+/// `MAX_BODY_SIZE` rather than buffering all of it.
+pub fn read_body<R: Read>(body: R) -> Result<String, BodyError> {
+    let mut bytes = Vec::new();
+    body.take(MAX_BODY_SIZE as u64 + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|err| BodyError::Read(err.to_string()))?;
+    if bytes.len() > MAX_BODY_SIZE {
+        return Err(BodyError::TooLarge);
+    }
+    String::from_utf8(bytes).map_err(|_| BodyError::NotUtf8)
+}
+
+/// The outcome for one address of a batch: either a check result or the
+/// reason the entry could not be checked.
+#[derive(serde::Serialize, Debug)]
+pub struct BatchItem {
+    /// The entry exactly as it appeared in the request.
+    pub query: String,
+    #[serde(flatten)]
+    pub result: Option<IpCheckResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Reads the addresses of a batch request.
+///
+/// The body is either a JSON array of strings or newline-delimited text, as
+/// the content type says. Without one, a body starting with `[` is JSON.
+pub fn parse_body(body: &str, content_type: Option<&str>) -> Result<Vec<String>> {
+    let is_json = match content_type {
+        Some(ct) => ct.starts_with("application/json"),
+        None => body.trim_start().starts_with('['),
+    };
+
+    let too_many = || anyhow!("Batch exceeds the limit of {} addresses", MAX_BATCH_SIZE);
+    if is_json {
+        let items: Vec<String> = serde_json::from_str(body)?;
+        if items.len() > MAX_BATCH_SIZE {
+            return Err(too_many());
+        }
+        return Ok(items);
+    }
+
+    let mut items = Vec::new();
+    for line in body.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if items.len() == MAX_BATCH_SIZE {
+            return Err(too_many());
+        }
+        items.push(line.to_owned());
+    }
+    Ok(items)
+}
+
+/// Checks one entry, recording why it was rejected if it is not an address.
+pub fn check_item(item: &str, matcher: &IpMatcher) -> BatchItem {
+    match addr::parse_addr(item) {
+        Ok(addr) => BatchItem {
+            query: item.to_owned(),
+            result: Some(service::check_ip(addr, matcher)),
+            error: None,
+        },
+        Err(err) => BatchItem {
+            query: item.to_owned(),
+            result: None,
+            error: Some(err.to_string()),
+        },
+    }
+}
+
+/// Checks every address of a batch against the same compiled list.
+pub fn check_batch(items: &[String], matcher: &IpMatcher) -> Vec<BatchItem> {
+    items.iter().map(|item| check_item(item, matcher)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_json_and_text() {
+        let expected = vec!["151.101.0.1".to_owned(), "8.8.8.8".to_owned()];
+
+        assert_eq!(
+            parse_body(r#"["151.101.0.1", "8.8.8.8"]"#, None).unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse_body("151.101.0.1\r\n\n 8.8.8.8 \n", Some("text/plain")).unwrap(),
+            expected
+        );
+        assert!(parse_body(r#"[1, 2]"#, Some("application/json")).is_err());
+    }
+
+    #[test]
+    fn text_is_never_sniffed() {
+        assert_eq!(
+            parse_body("[2a04:4e42::1]\n8.8.8.8", Some("text/plain; charset=utf-8")).unwrap(),
+            vec!["[2a04:4e42::1]".to_owned(), "8.8.8.8".to_owned()]
+        );
+        assert!(parse_body("[2a04:4e42::1]\n8.8.8.8", None).is_err());
+    }
+
+    #[test]
+    fn limits() {
+        let lines = "8.8.8.8\n".repeat(MAX_BATCH_SIZE);
+        assert_eq!(parse_body(&lines, None).unwrap().len(), MAX_BATCH_SIZE);
+        assert!(parse_body(&format!("{}1.1.1.1\n", lines), None).is_err());
+
+        let body = vec![b' '; MAX_BODY_SIZE];
+        assert_eq!(read_body(&body[..]).unwrap().len(), MAX_BODY_SIZE);
+        let body = vec![b' '; MAX_BODY_SIZE + 1];
+        assert_eq!(read_body(&body[..]), Err(BodyError::TooLarge));
+        assert_eq!(read_body(&b"\xff"[..]), Err(BodyError::NotUtf8));
+    }
+
+    #[test]
+    fn per_item_errors() {
+        let matcher = IpMatcher::new(vec!["151.101.0.0/16".parse().unwrap()], vec![]);
+        let items = vec!["151.101.0.1".to_owned(), "nope".to_owned()];
+        let results = check_batch(&items, &matcher);
+
+        assert_eq!(
+            serde_json::to_string(&results).unwrap(),
+            concat!(
+                r#"[{"query":"151.101.0.1","is_fastly_ip":true,"ip":"151.101.0.1","#,
+                r#""family":"ipv4","matched_prefix":"151.101.0.0/16"},"#,
+                r#"{"query":"nope","error":"\"nope\" is not a valid IPv4 or IPv6 address"}]"#
+            )
+        );
+    }
+}
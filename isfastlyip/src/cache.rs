@@ -0,0 +1,108 @@
+/// The Edge Dictionary the cache settings are read from.
+pub const CONFIG_DICTIONARY: &str = "isfastlyip_config";
+
+/// Surrogate key tagging the cached IP list, so that purging it forces the
+/// next request to fetch a fresh copy.
+pub const SURROGATE_KEY: &str = "public-ip-list";
+
+const HOUR: u32 = 60 * 60;
+const DAY: u32 = 24 * HOUR;
+
+/// How long the fetched IP list is cached, in seconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CacheSettings {
+    /// How long the list is fresh.
+    pub ttl: u32,
+    /// How long past `ttl` the cache serves a stale list while it is
+    /// refetched.
+    pub stale_while_revalidate: u32,
+    /// How long past `ttl` the last version recorded in the history is
+    /// served, counted from the last refresh that saw it, when the list
+    /// cannot be fetched.
+    pub stale_if_error: u32,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        CacheSettings {
+            ttl: HOUR,
+            stale_while_revalidate: DAY,
+            stale_if_error: 7 * DAY,
+        }
+    }
+}
+
+impl CacheSettings {
+    /// Reads the settings from `ip_list_ttl`, `ip_list_stale_while_revalidate`
+    /// and `ip_list_stale_if_error`. Missing or unparsable values keep their
+    /// default.
+    pub fn from_lookup<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let seconds = |key: &str, default: u32| {
+            lookup(key)
+                .and_then(|value| value.trim().parse().ok())
+                .unwrap_or(default)
+        };
+        let default = CacheSettings::default();
+        CacheSettings {
+            ttl: seconds("ip_list_ttl", default.ttl),
+            stale_while_revalidate: seconds(
+                "ip_list_stale_while_revalidate",
+                default.stale_while_revalidate,
+            ),
+            stale_if_error: seconds("ip_list_stale_if_error", default.stale_if_error),
+        }
+    }
+
+    /// Whether a recorded version last seen `age` seconds ago may still be
+    /// served because the list cannot be fetched.
+    pub fn serves_if_error(&self, age: i64) -> bool {
+        age <= i64::from(self.ttl) + i64::from(self.stale_if_error)
+    }
+
+    /// Whether data of the given age is past its TTL.
+    pub fn is_stale(&self, age: u32) -> bool {
+        age > self.ttl
+    }
+}
+
+/// Reads an `Age` header, the number of seconds a response has spent in the
+/// cache since it was fetched.
+pub fn parse_age(header: Option<&str>) -> Option<u32> {
+    header.and_then(|age| age.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_from_lookup() {
+        let settings = CacheSettings::from_lookup(|key| match key {
+            "ip_list_ttl" => Some("300".to_owned()),
+            "ip_list_stale_while_revalidate" => Some("soon".to_owned()),
+            _ => None,
+        });
+
+        assert_eq!(
+            settings,
+            CacheSettings {
+                ttl: 300,
+                ..CacheSettings::default()
+            }
+        );
+        assert!(settings.serves_if_error(300 + 7 * DAY as i64));
+        assert!(!settings.serves_if_error(301 + 7 * DAY as i64));
+        assert!(settings.is_stale(301));
+        assert!(!settings.is_stale(300));
+    }
+
+    #[test]
+    fn age_header() {
+        assert_eq!(parse_age(Some("42")), Some(42));
+        assert_eq!(parse_age(Some("-1")), None);
+        assert_eq!(parse_age(None), None);
+    }
+}
@@ -0,0 +1,293 @@
+use anyhow::{bail, Result};
+use ipnet::{IpNet, IpSubnets, Ipv4Subnets, Ipv6Subnets};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::matcher::IpMatcher;
+use crate::response::AddressFamily;
+
+/// How much of a queried network falls inside the list.
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Coverage {
+    /// Every address of the network is listed.
+    Covered,
+    /// Some, but not all, addresses of the network are listed.
+    Partial,
+    /// No address of the network is listed.
+    Disjoint,
+}
+
+impl Coverage {
+    /// The name the coverage is serialized as.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Coverage::Covered => "covered",
+            Coverage::Partial => "partial",
+            Coverage::Disjoint => "disjoint",
+        }
+    }
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct NetCheckResult {
+    /// The queried network with its host bits cleared.
+    pub network: IpNet,
+    pub family: AddressFamily,
+    pub coverage: Coverage,
+    /// Listed prefixes that contain or are contained in the network.
+    pub intersecting_prefixes: Vec<IpNet>,
+}
+
+/// An inclusive range of addresses.
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct AddrRange {
+    pub start: IpAddr,
+    pub end: IpAddr,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct RangeCheckResult {
+    pub start: IpAddr,
+    pub end: IpAddr,
+    pub family: AddressFamily,
+    /// Number of addresses in the range, saturating at `u128::MAX` for the
+    /// whole IPv6 space.
+    pub total_addresses: u128,
+    pub fastly_addresses: u128,
+    pub fastly_percentage: f64,
+    /// Sub-ranges whose addresses are all listed.
+    pub fastly_ranges: Vec<AddrRange>,
+    /// Sub-ranges whose addresses are all outside the list.
+    pub other_ranges: Vec<AddrRange>,
+}
+
+fn addr_value(addr: IpAddr) -> u128 {
+    match addr {
+        IpAddr::V4(ipv4) => u128::from(u32::from(ipv4)),
+        IpAddr::V6(ipv6) => u128::from(ipv6),
+    }
+}
+
+fn value_addr(value: u128, family: AddressFamily) -> IpAddr {
+    match family {
+        AddressFamily::Ipv4 => IpAddr::V4(Ipv4Addr::from(value as u32)),
+        AddressFamily::Ipv6 => IpAddr::V6(Ipv6Addr::from(value)),
+    }
+}
+
+fn range_len(start: u128, end: u128) -> u128 {
+    (end - start).saturating_add(1)
+}
+
+/// The first and last address of `net` as integers, inclusive.
+pub(crate) fn net_bounds(net: &IpNet) -> (u128, u128) {
+    (addr_value(net.network()), addr_value(net.broadcast()))
+}
+
+/// Sorts inclusive ranges and merges the overlapping or adjacent ones.
+pub(crate) fn merge_ranges(mut ranges: Vec<(u128, u128)>) -> Vec<(u128, u128)> {
+    ranges.sort_unstable();
+
+    let mut merged: Vec<(u128, u128)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Checks how much of `net` is covered by the compiled list.
+pub fn check_net(net: IpNet, matcher: &IpMatcher) -> NetCheckResult {
+    let network = net.trunc();
+    let intersecting_prefixes = matcher.intersecting(network);
+    let (start, end) = net_bounds(&network);
+
+    // Prefixes either contain the queried network or lie inside it, so the
+    // merged ranges only need to be compared against its bounds.
+    let merged = merge_ranges(intersecting_prefixes.iter().map(net_bounds).collect());
+    let coverage = match merged.as_slice() {
+        [] => Coverage::Disjoint,
+        [(first, last)] if *first <= start && *last >= end => Coverage::Covered,
+        _ => Coverage::Partial,
+    };
+
+    NetCheckResult {
+        network,
+        family: AddressFamily::of(network.addr()),
+        coverage,
+        intersecting_prefixes,
+    }
+}
+
+/// Checks which addresses between `start` and `end`, inclusive, are covered
+/// by the compiled list.
+pub fn check_range(start: IpAddr, end: IpAddr, matcher: &IpMatcher) -> Result<RangeCheckResult> {
+    let family = AddressFamily::of(start);
+    if family != AddressFamily::of(end) {
+        bail!("Range mixes IPv4 and IPv6 addresses");
+    }
+    let (first, last) = (addr_value(start), addr_value(end));
+    if first > last {
+        bail!("Range starts after it ends");
+    }
+
+    let subnets = match (start, end) {
+        (IpAddr::V4(start), IpAddr::V4(end)) => IpSubnets::from(Ipv4Subnets::new(start, end, 0)),
+        (IpAddr::V6(start), IpAddr::V6(end)) => IpSubnets::from(Ipv6Subnets::new(start, end, 0)),
+        _ => unreachable!(),
+    };
+    let bounds = subnets
+        .flat_map(|subnet| matcher.intersecting(subnet))
+        .map(|net| net_bounds(&net))
+        .map(|(start, end)| (start.max(first), end.min(last)))
+        .collect();
+
+    let mut fastly_ranges = Vec::new();
+    let mut other_ranges = Vec::new();
+    let mut fastly_addresses = 0u128;
+    let mut next = Some(first);
+    for (start, end) in merge_ranges(bounds) {
+        if let Some(gap_start) = next.filter(|gap_start| *gap_start < start) {
+            other_ranges.push(AddrRange {
+                start: value_addr(gap_start, family),
+                end: value_addr(start - 1, family),
+            });
+        }
+        fastly_ranges.push(AddrRange {
+            start: value_addr(start, family),
+            end: value_addr(end, family),
+        });
+        fastly_addresses = fastly_addresses.saturating_add(range_len(start, end));
+        next = end.checked_add(1);
+    }
+    if let Some(gap_start) = next.filter(|gap_start| *gap_start <= last) {
+        other_ranges.push(AddrRange {
+            start: value_addr(gap_start, family),
+            end,
+        });
+    }
+
+    let total_addresses = range_len(first, last);
+    Ok(RangeCheckResult {
+        start,
+        end,
+        family,
+        total_addresses,
+        fastly_addresses,
+        fastly_percentage: fastly_addresses as f64 / total_addresses as f64 * 100.0,
+        fastly_ranges,
+        other_ranges,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher() -> IpMatcher {
+        IpMatcher::new(
+            vec![
+                "151.101.0.0/16".parse().unwrap(),
+                "199.232.0.0/16".parse().unwrap(),
+                "104.156.80.0/21".parse().unwrap(),
+                "104.156.88.0/21".parse().unwrap(),
+            ],
+            vec!["2a04:4e42::/32".parse().unwrap()],
+        )
+    }
+
+    #[test]
+    fn covered_networks() {
+        let result = check_net("151.101.64.0/22".parse().unwrap(), &matcher());
+        assert_eq!(result.coverage, Coverage::Covered);
+        assert_eq!(
+            result.intersecting_prefixes,
+            vec!["151.101.0.0/16".parse::<IpNet>().unwrap()]
+        );
+
+        // Adjacent prefixes together cover the whole network.
+        let result = check_net("104.156.80.0/20".parse().unwrap(), &matcher());
+        assert_eq!(result.coverage, Coverage::Covered);
+        assert_eq!(result.intersecting_prefixes.len(), 2);
+
+        let result = check_net("2a04:4e42::/48".parse().unwrap(), &matcher());
+        assert_eq!(result.coverage, Coverage::Covered);
+        assert_eq!(result.family, AddressFamily::Ipv6);
+    }
+
+    #[test]
+    fn partial_and_disjoint_networks() {
+        let result = check_net("151.100.0.0/15".parse().unwrap(), &matcher());
+        assert_eq!(result.coverage, Coverage::Partial);
+        assert_eq!(result.network, "151.100.0.0/15".parse::<IpNet>().unwrap());
+
+        let result = check_net("8.8.8.8/24".parse().unwrap(), &matcher());
+        assert_eq!(result.coverage, Coverage::Disjoint);
+        assert_eq!(result.network, "8.8.8.0/24".parse::<IpNet>().unwrap());
+        assert!(result.intersecting_prefixes.is_empty());
+    }
+
+    #[test]
+    fn range_coverage() {
+        let result = check_range(
+            "151.100.255.0".parse().unwrap(),
+            "151.101.0.255".parse().unwrap(),
+            &matcher(),
+        )
+        .unwrap();
+
+        assert_eq!(result.total_addresses, 512);
+        assert_eq!(result.fastly_addresses, 256);
+        assert!((result.fastly_percentage - 50.0).abs() < f64::EPSILON);
+        assert_eq!(
+            result.fastly_ranges,
+            vec![AddrRange {
+                start: "151.101.0.0".parse().unwrap(),
+                end: "151.101.0.255".parse().unwrap(),
+            }]
+        );
+        assert_eq!(
+            result.other_ranges,
+            vec![AddrRange {
+                start: "151.100.255.0".parse().unwrap(),
+                end: "151.100.255.255".parse().unwrap(),
+            }]
+        );
+    }
+
+    #[test]
+    fn range_with_gaps() {
+        let result = check_range(
+            "104.156.79.0".parse().unwrap(),
+            "104.156.96.255".parse().unwrap(),
+            &matcher(),
+        )
+        .unwrap();
+
+        assert_eq!(result.fastly_addresses, 4096);
+        assert_eq!(result.fastly_ranges.len(), 1);
+        assert_eq!(result.other_ranges.len(), 2);
+
+        let result = check_range("::".parse().unwrap(), "1::".parse().unwrap(), &matcher());
+        assert_eq!(result.unwrap().fastly_addresses, 0);
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        let matcher = matcher();
+        assert!(check_range(
+            "10.0.0.2".parse().unwrap(),
+            "10.0.0.1".parse().unwrap(),
+            &matcher
+        )
+        .is_err());
+        assert!(check_range(
+            "10.0.0.1".parse().unwrap(),
+            "::1".parse().unwrap(),
+            &matcher
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,238 @@
+use anyhow::{bail, Result};
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+use crate::history::MIN_HASH_PREFIX;
+use crate::list::IpRanges;
+use crate::render::Render;
+
+/// A version of the list a diff can be taken against.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ListRef {
+    /// The list currently published by the API.
+    Live,
+    /// The snapshot compiled into the binary.
+    Snapshot,
+    /// A version from the history, by a prefix of its hash.
+    Version(String),
+}
+
+impl ListRef {
+    pub fn parse(input: &str) -> Result<Self> {
+        match input {
+            "live" => Ok(ListRef::Live),
+            "snapshot" => Ok(ListRef::Snapshot),
+            _ if input.len() >= MIN_HASH_PREFIX
+                && input.len() <= 64
+                && input
+                    .bytes()
+                    .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) =>
+            {
+                Ok(ListRef::Version(input.to_owned()))
+            }
+            _ => bail!(
+                "Unknown list {:?}, expected live, snapshot or a version hash",
+                input
+            ),
+        }
+    }
+}
+
+/// How the prefixes of one address family changed.
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct FamilyDiff<N> {
+    pub added: Vec<N>,
+    pub removed: Vec<N>,
+    pub unchanged: Vec<N>,
+}
+
+fn diff_family<N: Ord + Copy>(from: &[N], to: &[N]) -> FamilyDiff<N> {
+    let from: BTreeSet<N> = from.iter().cloned().collect();
+    let to: BTreeSet<N> = to.iter().cloned().collect();
+    FamilyDiff {
+        added: to.difference(&from).cloned().collect(),
+        removed: from.difference(&to).cloned().collect(),
+        unchanged: from.intersection(&to).cloned().collect(),
+    }
+}
+
+/// The prefixes that differ between two versions of the list.
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct ListDiff {
+    pub from: String,
+    pub to: String,
+    pub ipv4: FamilyDiff<Ipv4Net>,
+    pub ipv6: FamilyDiff<Ipv6Net>,
+}
+
+impl ListDiff {
+    /// Compares two normalized lists, labelled for display.
+    pub fn new(from: String, from_ranges: &IpRanges, to: String, to_ranges: &IpRanges) -> Self {
+        ListDiff {
+            from,
+            to,
+            ipv4: diff_family(&from_ranges.ipv4, &to_ranges.ipv4),
+            ipv6: diff_family(&from_ranges.ipv6, &to_ranges.ipv6),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ipv4.added.is_empty()
+            && self.ipv4.removed.is_empty()
+            && self.ipv6.added.is_empty()
+            && self.ipv6.removed.is_empty()
+    }
+
+    /// Every prefix in address order, with how it changed.
+    fn lines(&self) -> Vec<(Change, IpNet)> {
+        fn family<N: Copy + Into<IpNet> + Ord>(diff: &FamilyDiff<N>) -> Vec<(Change, IpNet)> {
+            let mut lines: Vec<_> = diff
+                .added
+                .iter()
+                .map(|net| (*net, Change::Added))
+                .chain(diff.removed.iter().map(|net| (*net, Change::Removed)))
+                .chain(diff.unchanged.iter().map(|net| (*net, Change::Unchanged)))
+                .collect();
+            lines.sort_by_key(|(net, _)| *net);
+            lines
+                .into_iter()
+                .map(|(net, change)| (change, net.into()))
+                .collect()
+        }
+
+        let mut lines = family(&self.ipv4);
+        lines.extend(family(&self.ipv6));
+        lines
+    }
+}
+
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Change {
+    Added,
+    Removed,
+    Unchanged,
+}
+
+impl Change {
+    fn as_str(self) -> &'static str {
+        match self {
+            Change::Added => "added",
+            Change::Removed => "removed",
+            Change::Unchanged => "unchanged",
+        }
+    }
+
+    /// The prefix of a unified diff line.
+    fn symbol(self) -> char {
+        match self {
+            Change::Added => '+',
+            Change::Removed => '-',
+            Change::Unchanged => ' ',
+        }
+    }
+}
+
+impl Render for ListDiff {
+    /// A unified diff of the two lists, one prefix per line.
+    fn text(&self) -> String {
+        let mut diff = format!("--- {}\n+++ {}\n", self.from, self.to);
+        for (change, net) in self.lines() {
+            diff.push(change.symbol());
+            diff.push_str(&net.to_string());
+            diff.push('\n');
+        }
+        diff
+    }
+
+    fn csv_header(&self) -> &'static [&'static str] {
+        &["family", "change", "prefix"]
+    }
+
+    fn csv_rows(&self) -> Vec<Vec<String>> {
+        self.lines()
+            .into_iter()
+            .map(|(change, net)| {
+                let family = match net {
+                    IpNet::V4(_) => "ipv4",
+                    IpNet::V6(_) => "ipv6",
+                };
+                vec![
+                    family.to_owned(),
+                    change.as_str().to_owned(),
+                    net.to_string(),
+                ]
+            })
+            .collect()
+    }
+
+    fn ndjson(&self) -> Result<String> {
+        #[derive(Serialize)]
+        struct Line {
+            change: Change,
+            prefix: IpNet,
+        }
+
+        let mut ndjson = String::new();
+        for (change, prefix) in self.lines() {
+            ndjson.push_str(&serde_json::to_string(&Line { change, prefix })?);
+            ndjson.push('\n');
+        }
+        Ok(ndjson)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::Format;
+
+    fn ranges(nets: &[&str]) -> IpRanges {
+        let mut ranges = IpRanges::default();
+        for net in nets {
+            ranges.push(net.parse().unwrap());
+        }
+        ranges
+    }
+
+    fn diff() -> ListDiff {
+        ListDiff::new(
+            "snapshot 2021-03-19".to_owned(),
+            &ranges(&["151.101.0.0/16", "185.31.16.0/22", "2a04:4e40::/32"]),
+            "live".to_owned(),
+            &ranges(&["146.75.0.0/17", "151.101.0.0/16", "2a04:4e40::/32"]),
+        )
+    }
+
+    #[test]
+    fn families() {
+        let diff = diff();
+        assert!(!diff.is_empty());
+        assert_eq!(diff.ipv4.added, vec!["146.75.0.0/17".parse().unwrap()]);
+        assert_eq!(diff.ipv4.removed, vec!["185.31.16.0/22".parse().unwrap()]);
+        assert_eq!(diff.ipv4.unchanged, vec!["151.101.0.0/16".parse().unwrap()]);
+        assert!(diff.ipv6.added.is_empty() && diff.ipv6.removed.is_empty());
+    }
+
+    #[test]
+    fn unified_text() {
+        assert_eq!(
+            diff().render(Format::Text).unwrap(),
+            concat!(
+                "--- snapshot 2021-03-19\n",
+                "+++ live\n",
+                "+146.75.0.0/17\n",
+                " 151.101.0.0/16\n",
+                "-185.31.16.0/22\n",
+                " 2a04:4e40::/32\n",
+            )
+        );
+        assert_eq!(
+            ListRef::parse("3f9a2c1").unwrap(),
+            ListRef::Version("3f9a2c1".to_owned())
+        );
+        assert!(ListRef::parse("yesterday").is_err());
+        assert!(ListRef::parse("3f9a2c").is_err());
+    }
+}
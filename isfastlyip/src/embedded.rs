@@ -0,0 +1,93 @@
+use ipnet::IpNet;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// IPv6 transition formats that carry an IPv4 address.
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Embedding {
+    /// `::ffff:a.b.c.d`
+    Ipv4Mapped,
+    /// `::a.b.c.d`, deprecated by RFC 4291.
+    Ipv4Compatible,
+    /// `2002:AABB:CCDD::/48`, from RFC 3056.
+    #[serde(rename = "6to4")]
+    SixToFour,
+    /// `2001:0::/32`, carrying the client address with its bits inverted.
+    Teredo,
+    /// `64:ff9b::a.b.c.d`, the well-known NAT64 prefix.
+    Nat64,
+}
+
+/// The IPv4 address found inside an IPv6 address, and how it matched.
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct EmbeddedIpv4 {
+    pub embedding: Embedding,
+    pub ip: Ipv4Addr,
+    pub is_fastly_ip: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matched_prefix: Option<IpNet>,
+}
+
+fn ipv4_from(high: u16, low: u16) -> Ipv4Addr {
+    Ipv4Addr::from(u32::from(high) << 16 | u32::from(low))
+}
+
+/// Extracts the IPv4 address embedded in `addr` by a transition format.
+pub fn unwrap_ipv4(addr: Ipv6Addr) -> Option<(Embedding, Ipv4Addr)> {
+    let segments = addr.segments();
+    match segments {
+        [0, 0, 0, 0, 0, 0xffff, high, low] => Some((Embedding::Ipv4Mapped, ipv4_from(high, low))),
+        // `::` and `::1` are the unspecified and loopback addresses, not
+        // IPv4-compatible ones.
+        [0, 0, 0, 0, 0, 0, high, low] if high != 0 || low > 1 => {
+            Some((Embedding::Ipv4Compatible, ipv4_from(high, low)))
+        }
+        [0x0064, 0xff9b, 0, 0, 0, 0, high, low] => Some((Embedding::Nat64, ipv4_from(high, low))),
+        [0x2002, high, low, ..] => Some((Embedding::SixToFour, ipv4_from(high, low))),
+        [0x2001, 0, .., high, low] => Some((Embedding::Teredo, ipv4_from(!high, !low))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unwrap(addr: &str) -> Option<(Embedding, Ipv4Addr)> {
+        unwrap_ipv4(addr.parse().unwrap())
+    }
+
+    #[test]
+    fn transition_formats() {
+        let fastly: Ipv4Addr = "151.101.1.1".parse().unwrap();
+
+        assert_eq!(
+            unwrap("::ffff:151.101.1.1"),
+            Some((Embedding::Ipv4Mapped, fastly))
+        );
+        assert_eq!(
+            unwrap("::151.101.1.1"),
+            Some((Embedding::Ipv4Compatible, fastly))
+        );
+        assert_eq!(
+            unwrap("64:ff9b::9765:0101"),
+            Some((Embedding::Nat64, fastly))
+        );
+        assert_eq!(
+            unwrap("2002:9765:0101::"),
+            Some((Embedding::SixToFour, fastly))
+        );
+        assert_eq!(
+            unwrap("2001:0:4136:e378:8000:63bf:689a:fefe"),
+            Some((Embedding::Teredo, fastly))
+        );
+    }
+
+    #[test]
+    fn plain_ipv6() {
+        assert_eq!(unwrap("::"), None);
+        assert_eq!(unwrap("::1"), None);
+        assert_eq!(unwrap("2a04:4e40::1"), None);
+        assert_eq!(unwrap("2001:db8::1"), None);
+    }
+}
@@ -0,0 +1,229 @@
+use anyhow::Result;
+use ipnet::IpNet;
+use serde_json::json;
+use std::fmt::Write;
+
+use crate::list::IpRanges;
+use crate::response::Reply;
+
+const HEADER: &str = "Fastly public IP ranges, from https://api.fastly.com/public-ip-list";
+
+/// The firewall and proxy configurations the list can be exported as.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    /// nginx `allow` directives, ending with `deny all`.
+    Nginx,
+    /// nginx `set_real_ip_from` directives trusting `Fastly-Client-IP`.
+    NginxRealIp,
+    /// An HAProxy ACL file, one network per line.
+    Haproxy,
+    /// An Apache `<RequireAny>` block of `Require ip` directives.
+    Apache,
+    /// `iptables` and `ip6tables` commands filling a `FASTLY` chain.
+    Iptables,
+    /// An nftables table with one interval set per family, flushed and
+    /// refilled on every load.
+    Nftables,
+    /// An `ipset restore` file with one `hash:net` set per family, flushed
+    /// and refilled on every restore.
+    Ipset,
+    /// AWS security group ingress permissions for HTTP and HTTPS.
+    AwsSecurityGroup,
+}
+
+pub const EXPORT_FORMATS: &[ExportFormat] = &[
+    ExportFormat::Nginx,
+    ExportFormat::NginxRealIp,
+    ExportFormat::Haproxy,
+    ExportFormat::Apache,
+    ExportFormat::Iptables,
+    ExportFormat::Nftables,
+    ExportFormat::Ipset,
+    ExportFormat::AwsSecurityGroup,
+];
+
+impl ExportFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            ExportFormat::Nginx => "nginx",
+            ExportFormat::NginxRealIp => "nginx-real-ip",
+            ExportFormat::Haproxy => "haproxy",
+            ExportFormat::Apache => "apache",
+            ExportFormat::Iptables => "iptables",
+            ExportFormat::Nftables => "nftables",
+            ExportFormat::Ipset => "ipset",
+            ExportFormat::AwsSecurityGroup => "aws-security-group",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        EXPORT_FORMATS
+            .iter()
+            .cloned()
+            .find(|format| format.name() == name)
+    }
+
+    /// The name the export is conventionally saved under.
+    pub fn file_name(self) -> &'static str {
+        match self {
+            ExportFormat::Nginx => "fastly-allow.conf",
+            ExportFormat::NginxRealIp => "fastly-real-ip.conf",
+            ExportFormat::Haproxy => "fastly.acl",
+            ExportFormat::Apache => "fastly-require.conf",
+            ExportFormat::Iptables => "fastly-iptables.sh",
+            ExportFormat::Nftables => "fastly.nft",
+            ExportFormat::Ipset => "fastly.ipset",
+            ExportFormat::AwsSecurityGroup => "fastly-ip-permissions.json",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::AwsSecurityGroup => "application/json",
+            _ => "text/plain; charset=utf-8",
+        }
+    }
+
+    /// Renders the ranges, IPv4 networks first.
+    pub fn render(self, ranges: &IpRanges) -> Result<String> {
+        let nets = || {
+            ranges
+                .ipv4
+                .iter()
+                .map(|net| IpNet::V4(*net))
+                .chain(ranges.ipv6.iter().map(|net| IpNet::V6(*net)))
+        };
+        let ipv4: Vec<String> = ranges.ipv4.iter().map(ToString::to_string).collect();
+        let ipv6: Vec<String> = ranges.ipv6.iter().map(ToString::to_string).collect();
+
+        let mut out = String::new();
+        match self {
+            ExportFormat::Nginx => {
+                writeln!(out, "# {}", HEADER)?;
+                for net in nets() {
+                    writeln!(out, "allow {};", net)?;
+                }
+                writeln!(out, "deny all;")?;
+            }
+            ExportFormat::NginxRealIp => {
+                writeln!(out, "# {}", HEADER)?;
+                for net in nets() {
+                    writeln!(out, "set_real_ip_from {};", net)?;
+                }
+                writeln!(out, "real_ip_header Fastly-Client-IP;")?;
+            }
+            ExportFormat::Haproxy => {
+                writeln!(out, "# {}", HEADER)?;
+                for net in nets() {
+                    writeln!(out, "{}", net)?;
+                }
+            }
+            ExportFormat::Apache => {
+                writeln!(out, "# {}", HEADER)?;
+                writeln!(out, "<RequireAny>")?;
+                for net in nets() {
+                    writeln!(out, "    Require ip {}", net)?;
+                }
+                writeln!(out, "</RequireAny>")?;
+            }
+            ExportFormat::Iptables => {
+                // Safe to rerun: the chain is emptied rather than created
+                // again, and the jump to it is only added once.
+                let jump = "INPUT -p tcp -m multiport --dports 80,443 -j FASTLY";
+                writeln!(out, "#!/bin/sh")?;
+                writeln!(out, "# {}", HEADER)?;
+                writeln!(
+                    out,
+                    "# Only Fastly may reach ports 80 and 443; other traffic is dropped."
+                )?;
+                writeln!(out, "set -e")?;
+                for (command, nets) in &[("iptables", &ipv4), ("ip6tables", &ipv6)] {
+                    writeln!(out, "{0} -N FASTLY 2>/dev/null || {0} -F FASTLY", command)?;
+                    for net in nets.iter() {
+                        writeln!(out, "{} -A FASTLY -s {} -j ACCEPT", command, net)?;
+                    }
+                    writeln!(out, "{} -A FASTLY -j DROP", command)?;
+                    writeln!(out, "{0} -C {1} 2>/dev/null || {0} -I {1}", command, jump)?;
+                }
+            }
+            ExportFormat::Nftables => {
+                writeln!(out, "# {}", HEADER)?;
+                writeln!(out, "table inet fastly {{")?;
+                let sets = [
+                    ("fastly_ipv4", "ipv4_addr", &ipv4),
+                    ("fastly_ipv6", "ipv6_addr", &ipv6),
+                ];
+                for (index, (name, addr_type, _)) in sets.iter().enumerate() {
+                    if index > 0 {
+                        writeln!(out)?;
+                    }
+                    writeln!(out, "\tset {} {{", name)?;
+                    writeln!(out, "\t\ttype {}", addr_type)?;
+                    writeln!(out, "\t\tflags interval")?;
+                    writeln!(out, "\t}}")?;
+                }
+                writeln!(out, "}}")?;
+                for (name, _, nets) in sets.iter() {
+                    writeln!(out, "flush set inet fastly {}", name)?;
+                    if !nets.is_empty() {
+                        writeln!(out, "add element inet fastly {} {{", name)?;
+                        for net in nets.iter() {
+                            writeln!(out, "\t{},", net)?;
+                        }
+                        writeln!(out, "}}")?;
+                    }
+                }
+            }
+            ExportFormat::Ipset => {
+                writeln!(out, "# {}", HEADER)?;
+                for (name, family, nets) in &[
+                    ("fastly_ipv4", "inet", &ipv4),
+                    ("fastly_ipv6", "inet6", &ipv6),
+                ] {
+                    writeln!(out, "create {} hash:net family {} -exist", name, family)?;
+                    writeln!(out, "flush {}", name)?;
+                    for net in nets.iter() {
+                        writeln!(out, "add {} {} -exist", name, net)?;
+                    }
+                }
+            }
+            ExportFormat::AwsSecurityGroup => {
+                let permissions: Vec<_> = [80, 443]
+                    .iter()
+                    .map(|port| {
+                        json!({
+                            "IpProtocol": "tcp",
+                            "FromPort": port,
+                            "ToPort": port,
+                            "IpRanges": ipv4
+                                .iter()
+                                .map(|net| json!({"CidrIp": net, "Description": "Fastly"}))
+                                .collect::<Vec<_>>(),
+                            "Ipv6Ranges": ipv6
+                                .iter()
+                                .map(|net| json!({"CidrIpv6": net, "Description": "Fastly"}))
+                                .collect::<Vec<_>>(),
+                        })
+                    })
+                    .collect();
+                out = serde_json::to_string_pretty(&permissions)?;
+                out.push('\n');
+            }
+        }
+        Ok(out)
+    }
+
+    /// Renders the ranges as a reply, named so that browsers offer to save it
+    /// under [`ExportFormat::file_name`].
+    pub fn reply(self, ranges: &IpRanges) -> Result<Reply> {
+        Ok(Reply {
+            status: 200,
+            content_type: self.content_type(),
+            headers: vec![(
+                "content-disposition",
+                format!("inline; filename=\"{}\"", self.file_name()),
+            )],
+            body: self.render(ranges)?,
+        })
+    }
+}
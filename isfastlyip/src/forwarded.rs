@@ -0,0 +1,206 @@
+use std::net::IpAddr;
+
+use crate::addr;
+use crate::batch::{self, BatchItem};
+use crate::matcher::IpMatcher;
+
+/// The forwarding headers of a request, as received by an origin.
+#[derive(serde::Deserialize, Debug, Default)]
+pub struct ForwardedChain {
+    /// The `X-Forwarded-For` header, client first.
+    #[serde(default)]
+    pub x_forwarded_for: Option<String>,
+    /// The `Fastly-Client-IP` header.
+    #[serde(default)]
+    pub fastly_client_ip: Option<String>,
+    /// The address that connected to the origin, appended as the last hop.
+    #[serde(default)]
+    pub peer: Option<IpAddr>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct ChainAnalysis {
+    /// Every hop of the chain, client first, ending with the peer.
+    pub hops: Vec<BatchItem>,
+    /// The first hop that is not a Fastly node, walking back from the peer.
+    pub client_ip: Option<IpAddr>,
+    /// Whether the request reached the origin through Fastly at all.
+    pub via_fastly: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fastly_client_ip: Option<String>,
+    /// Set when `Fastly-Client-IP` is present but was not set by Fastly: the
+    /// request did not arrive through Fastly, or the header disagrees with
+    /// the client found by walking the chain.
+    pub fastly_client_ip_spoofed: bool,
+}
+
+/// Drops the port some proxies append to a hop, as in `203.0.113.7:5678` or
+/// `[2001:db8::1]:443`. A bare IPv6 address is left alone.
+fn strip_port(hop: &str) -> &str {
+    if hop.starts_with('[') {
+        if let Some(end) = hop.find("]:") {
+            return &hop[..=end];
+        }
+    } else if hop.matches(':').count() == 1 {
+        return hop.split(':').next().unwrap_or(hop);
+    }
+    hop
+}
+
+/// Classifies every hop of a forwarded chain and finds the real client.
+///
+/// Hops are trusted from the peer backwards only while they are Fastly
+/// nodes; the first other hop is the client. An unparsable hop ends the walk
+/// without a client, since nothing before it can be trusted.
+pub fn analyze_chain(chain: &ForwardedChain, matcher: &IpMatcher) -> ChainAnalysis {
+    let mut entries: Vec<String> = chain
+        .x_forwarded_for
+        .iter()
+        .flat_map(|header| header.split(','))
+        .map(|hop| hop.trim().to_owned())
+        .filter(|hop| !hop.is_empty())
+        .collect();
+    if let Some(peer) = chain.peer {
+        entries.push(peer.to_string());
+    }
+
+    let hops: Vec<BatchItem> = entries
+        .iter()
+        .map(|entry| BatchItem {
+            query: entry.clone(),
+            ..batch::check_item(strip_port(entry), matcher)
+        })
+        .collect();
+
+    let mut client_ip = None;
+    let mut via_fastly = false;
+    for hop in hops.iter().rev() {
+        match &hop.result {
+            Some(result) if result.is_fastly_ip => {
+                via_fastly = true;
+                client_ip = Some(result.ip);
+            }
+            Some(result) => {
+                client_ip = Some(result.ip);
+                break;
+            }
+            None => {
+                client_ip = None;
+                break;
+            }
+        }
+    }
+
+    let fastly_client_ip_spoofed = match &chain.fastly_client_ip {
+        Some(header) => !via_fastly || addr::parse_addr(header).ok() != client_ip,
+        None => false,
+    };
+
+    ChainAnalysis {
+        hops,
+        client_ip,
+        via_fastly,
+        fastly_client_ip: chain.fastly_client_ip.clone(),
+        fastly_client_ip_spoofed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher() -> IpMatcher {
+        IpMatcher::new(vec!["151.101.0.0/16".parse().unwrap()], vec![])
+    }
+
+    #[test]
+    fn client_behind_fastly() {
+        let chain = ForwardedChain {
+            x_forwarded_for: Some("10.0.0.1, 203.0.113.7, 151.101.1.1".to_owned()),
+            fastly_client_ip: Some("203.0.113.7".to_owned()),
+            peer: Some("151.101.2.2".parse().unwrap()),
+        };
+        let analysis = analyze_chain(&chain, &matcher());
+
+        assert_eq!(analysis.hops.len(), 4);
+        assert_eq!(analysis.client_ip, Some("203.0.113.7".parse().unwrap()));
+        assert!(analysis.via_fastly);
+        assert!(!analysis.fastly_client_ip_spoofed);
+    }
+
+    #[test]
+    fn spoofed_fastly_client_ip() {
+        // Sent straight to the origin with a forged header.
+        let chain = ForwardedChain {
+            x_forwarded_for: None,
+            fastly_client_ip: Some("151.101.1.1".to_owned()),
+            peer: Some("198.51.100.1".parse().unwrap()),
+        };
+        let analysis = analyze_chain(&chain, &matcher());
+        assert_eq!(analysis.client_ip, Some("198.51.100.1".parse().unwrap()));
+        assert!(!analysis.via_fastly);
+        assert!(analysis.fastly_client_ip_spoofed);
+
+        // Through Fastly, but disagreeing with the forwarded chain.
+        let chain = ForwardedChain {
+            x_forwarded_for: Some("203.0.113.7".to_owned()),
+            fastly_client_ip: Some("192.0.2.1".to_owned()),
+            peer: Some("151.101.2.2".parse().unwrap()),
+        };
+        assert!(analyze_chain(&chain, &matcher()).fastly_client_ip_spoofed);
+    }
+
+    #[test]
+    fn hops_with_ports() {
+        let chain = ForwardedChain {
+            x_forwarded_for: Some("[2001:db8::1]:443, 203.0.113.7:5678, 151.101.1.1:80".to_owned()),
+            fastly_client_ip: Some(" 203.0.113.7 ".to_owned()),
+            peer: Some("151.101.2.2".parse().unwrap()),
+        };
+        let analysis = analyze_chain(&chain, &matcher());
+
+        assert_eq!(analysis.hops[0].query, "[2001:db8::1]:443");
+        assert_eq!(
+            analysis.hops[0].result.as_ref().map(|result| result.ip),
+            Some("2001:db8::1".parse().unwrap())
+        );
+        assert_eq!(analysis.client_ip, Some("203.0.113.7".parse().unwrap()));
+        assert!(analysis.via_fastly);
+        assert!(!analysis.fastly_client_ip_spoofed);
+    }
+
+    #[test]
+    fn strict_fastly_client_ip() {
+        // Parsed as strictly as the chain, so a zero-padded header does not
+        // pass for the client it resembles.
+        let chain = ForwardedChain {
+            x_forwarded_for: Some("203.0.113.7".to_owned()),
+            fastly_client_ip: Some("203.0.113.07".to_owned()),
+            peer: Some("151.101.2.2".parse().unwrap()),
+        };
+        assert!(analyze_chain(&chain, &matcher()).fastly_client_ip_spoofed);
+
+        let chain = ForwardedChain {
+            x_forwarded_for: Some("2001:db8::1".to_owned()),
+            fastly_client_ip: Some("[2001:db8::1]".to_owned()),
+            peer: Some("151.101.2.2".parse().unwrap()),
+        };
+        assert!(!analyze_chain(&chain, &matcher()).fastly_client_ip_spoofed);
+    }
+
+    #[test]
+    fn invalid_hop_stops_walk() {
+        let chain = ForwardedChain {
+            x_forwarded_for: Some("203.0.113.7, unknown".to_owned()),
+            fastly_client_ip: None,
+            peer: Some("151.101.2.2".parse().unwrap()),
+        };
+        let analysis = analyze_chain(&chain, &matcher());
+
+        assert_eq!(analysis.client_ip, None);
+        assert_eq!(
+            analysis.hops[1].error.as_deref(),
+            Some("\"unknown\" is not a valid IPv4 or IPv6 address")
+        );
+    }
+}
@@ -0,0 +1,340 @@
+//! Gatekeeper mode: instead of answering lookups, the service proxies every
+//! request to a backend, forwarding or blocking it depending on whether its
+//! address belongs to the configured providers.
+
+use anyhow::{bail, Result};
+use std::net::IpAddr;
+
+use crate::addr;
+use crate::matcher::IpMatcher;
+use crate::provider::{self, Provider, ProviderMatch};
+use crate::response::Reply;
+use crate::service;
+
+/// Whether the address is one of Fastly's: `true`, `false` or `unknown`.
+pub const FASTLY_IP_HEADER: &str = "x-is-fastly-ip";
+/// The configured provider the address belongs to, when it belongs to one.
+pub const PROVIDER_HEADER: &str = "x-ip-provider";
+/// The most specific prefix of that provider containing the address.
+pub const PREFIX_HEADER: &str = "x-ip-matched-prefix";
+
+/// The headers the gatekeeper sets. Copies sent by the client are removed
+/// before forwarding so that the backend can trust them.
+pub const ANNOTATION_HEADERS: &[&str] = &[FASTLY_IP_HEADER, PROVIDER_HEADER, PREFIX_HEADER];
+
+/// What happens to a request depending on its address.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// Only forward requests from the providers, blocking the others.
+    Allow,
+    /// Block requests from the providers, forwarding the others.
+    Deny,
+    /// Forward every request, only annotating it.
+    Annotate,
+}
+
+impl Action {
+    pub fn from_name(name: &str) -> Result<Self> {
+        Ok(match name {
+            "allow" => Action::Allow,
+            "deny" => Action::Deny,
+            "annotate" => Action::Annotate,
+            _ => bail!("Unsupported gatekeeper action {:?}", name),
+        })
+    }
+}
+
+/// Where the address of a request is read from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AddrSource {
+    /// The address the request was received from.
+    Client,
+    /// A header set by the hop in front of the service, such as
+    /// `Fastly-Client-IP`.
+    Header(String),
+}
+
+impl AddrSource {
+    /// Parses `client` or `header:<name>`.
+    pub fn from_name(name: &str) -> Result<Self> {
+        if name == "client" {
+            return Ok(AddrSource::Client);
+        }
+        match name.strip_prefix("header:").map(str::trim) {
+            Some(header) if !header.is_empty() => Ok(AddrSource::Header(header.to_lowercase())),
+            _ => bail!("Unsupported gatekeeper address source {:?}", name),
+        }
+    }
+}
+
+/// Reads the address from a header value. In a list such as
+/// `X-Forwarded-For` the last entry is used: it was added by the hop nearest
+/// to the service, while earlier ones are whatever the client sent.
+pub fn parse_header_addr(value: &str) -> Option<IpAddr> {
+    addr::parse_addr(value.rsplit(',').next()?).ok()
+}
+
+/// The gatekeeper configuration, read from the config dictionary.
+#[derive(Debug)]
+pub struct GatekeeperSettings {
+    /// The backend requests are forwarded to.
+    pub backend: String,
+    pub action: Action,
+    pub addr_source: AddrSource,
+    /// The providers whose addresses the action applies to.
+    pub providers: Vec<&'static Provider>,
+}
+
+impl GatekeeperSettings {
+    /// Returns `None` unless `gatekeeper_backend` is set, in which case the
+    /// other keys must be valid too: a mistyped action should not silently
+    /// expose the backend.
+    pub fn from_lookup<F>(lookup: F) -> Result<Option<Self>>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let backend = match lookup("gatekeeper_backend")
+            .map(|backend| backend.trim().to_owned())
+            .filter(|backend| !backend.is_empty())
+        {
+            Some(backend) => backend,
+            None => return Ok(None),
+        };
+        let action = match lookup("gatekeeper_action") {
+            Some(name) => Action::from_name(name.trim())?,
+            None => Action::Allow,
+        };
+        let addr_source = match lookup("gatekeeper_addr_source") {
+            Some(name) => AddrSource::from_name(name.trim())?,
+            None => AddrSource::Client,
+        };
+        let names =
+            lookup("gatekeeper_providers").unwrap_or_else(|| provider::FASTLY.name.to_owned());
+        let mut providers = Vec::new();
+        for name in names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            match provider::find(name) {
+                Some(provider) => providers.push(provider),
+                None => bail!("Unknown provider {:?}", name),
+            }
+        }
+        if providers.is_empty() {
+            bail!("No gatekeeper providers configured");
+        }
+        Ok(Some(GatekeeperSettings {
+            backend,
+            action,
+            addr_source,
+            providers,
+        }))
+    }
+}
+
+/// How a request was judged.
+#[derive(Debug, PartialEq)]
+pub struct Verdict {
+    /// `None` when the address was missing or unparseable.
+    pub addr: Option<IpAddr>,
+    pub is_fastly_ip: Option<bool>,
+    /// The first configured provider the address belongs to.
+    pub provider: Option<ProviderMatch>,
+}
+
+impl Verdict {
+    /// Checks `addr` against Fastly's list and the configured providers. An
+    /// IPv6 address embedding an IPv4 one belongs to whoever either of them
+    /// belongs to.
+    pub fn judge(
+        addr: Option<IpAddr>,
+        fastly: &IpMatcher,
+        providers: &[(&'static Provider, &IpMatcher)],
+    ) -> Self {
+        let provider = addr.and_then(|addr| {
+            providers.iter().find_map(|(provider, matcher)| {
+                service::lookup_embedded(addr, matcher).map(|matched_prefix| ProviderMatch {
+                    provider: provider.name,
+                    matched_prefix,
+                })
+            })
+        });
+        Verdict {
+            addr,
+            is_fastly_ip: addr.map(|addr| service::lookup_embedded(addr, fastly).is_some()),
+            provider,
+        }
+    }
+
+    /// Whether the request goes through to the backend. An unknown address
+    /// belongs to no provider.
+    pub fn forwards(&self, action: Action) -> bool {
+        match action {
+            Action::Allow => self.provider.is_some(),
+            Action::Deny => self.provider.is_none(),
+            Action::Annotate => true,
+        }
+    }
+
+    /// The annotation headers for the forwarded request.
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let is_fastly_ip = match self.is_fastly_ip {
+            Some(true) => "true",
+            Some(false) => "false",
+            None => "unknown",
+        };
+        let mut headers = vec![(FASTLY_IP_HEADER, is_fastly_ip.to_owned())];
+        if let Some(provider) = &self.provider {
+            headers.push((PROVIDER_HEADER, provider.provider.to_owned()));
+            headers.push((PREFIX_HEADER, provider.matched_prefix.to_string()));
+        }
+        headers
+    }
+
+    /// The `403 Forbidden` reply for a blocked request.
+    pub fn rejection(&self) -> Reply {
+        let body = match (&self.addr, &self.provider) {
+            (None, _) => "The address of the request is unknown".to_owned(),
+            (Some(addr), Some(provider)) => {
+                format!("{} belongs to {}", addr, provider.provider)
+            }
+            (Some(addr), None) => format!("{} belongs to none of the allowed providers", addr),
+        };
+        Reply::text(403, &body).with_headers(self.headers())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::ListFormat;
+
+    fn lookup(pairs: &'static [(&'static str, &'static str)]) -> impl Fn(&str) -> Option<String> {
+        move |key| {
+            pairs
+                .iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| (*value).to_owned())
+        }
+    }
+
+    #[test]
+    fn settings() {
+        assert!(GatekeeperSettings::from_lookup(|_| None).unwrap().is_none());
+
+        let settings = GatekeeperSettings::from_lookup(lookup(&[("gatekeeper_backend", "origin")]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(settings.backend, "origin");
+        assert_eq!(settings.action, Action::Allow);
+        assert_eq!(settings.addr_source, AddrSource::Client);
+        assert_eq!(settings.providers.len(), 1);
+        assert_eq!(settings.providers[0].name, "fastly");
+
+        let settings = GatekeeperSettings::from_lookup(lookup(&[
+            ("gatekeeper_backend", "origin"),
+            ("gatekeeper_action", "annotate"),
+            ("gatekeeper_addr_source", "header:Fastly-Client-IP"),
+            ("gatekeeper_providers", "fastly, cloudflare"),
+        ]))
+        .unwrap()
+        .unwrap();
+        assert_eq!(settings.action, Action::Annotate);
+        assert_eq!(
+            settings.addr_source,
+            AddrSource::Header("fastly-client-ip".to_owned())
+        );
+        assert_eq!(settings.providers.len(), 2);
+
+        for invalid in &[
+            ("gatekeeper_action", "block"),
+            ("gatekeeper_addr_source", "header:"),
+            ("gatekeeper_providers", "fastly,nope"),
+            ("gatekeeper_providers", ","),
+        ] {
+            let result = GatekeeperSettings::from_lookup(|key| match key {
+                "gatekeeper_backend" => Some("origin".to_owned()),
+                key if key == invalid.0 => Some(invalid.1.to_owned()),
+                _ => None,
+            });
+            assert!(result.is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn header_addr() {
+        assert_eq!(
+            parse_header_addr("203.0.113.7"),
+            Some("203.0.113.7".parse().unwrap())
+        );
+        assert_eq!(
+            parse_header_addr("1.1.1.1, 151.101.1.1"),
+            Some("151.101.1.1".parse().unwrap())
+        );
+        assert_eq!(
+            parse_header_addr("1.1.1.1, [2a04:4e42::1]"),
+            Some("2a04:4e42::1".parse().unwrap())
+        );
+        assert_eq!(
+            parse_header_addr("fe80::1%eth0"),
+            Some("fe80::1".parse().unwrap())
+        );
+        assert_eq!(parse_header_addr("151.101.1.1, junk"), None);
+        assert_eq!(parse_header_addr("151.101.001.1"), None);
+        assert_eq!(parse_header_addr(""), None);
+    }
+
+    #[test]
+    fn verdicts() {
+        let fastly = ListFormat::PlainText.parse(b"151.101.0.0/16").unwrap();
+        let cloudflare = ListFormat::PlainText.parse(b"104.16.0.0/13").unwrap();
+        let cloudflare = cloudflare.matcher();
+        let providers = vec![(provider::find("cloudflare").unwrap(), &cloudflare)];
+        let fastly = fastly.matcher();
+
+        let verdict = Verdict::judge("104.16.1.1".parse().ok(), &fastly, &providers);
+        assert!(verdict.forwards(Action::Allow));
+        assert!(!verdict.forwards(Action::Deny));
+        assert_eq!(
+            verdict.headers(),
+            vec![
+                (FASTLY_IP_HEADER, "false".to_owned()),
+                (PROVIDER_HEADER, "cloudflare".to_owned()),
+                (PREFIX_HEADER, "104.16.0.0/13".to_owned()),
+            ]
+        );
+
+        let verdict = Verdict::judge("151.101.1.1".parse().ok(), &fastly, &providers);
+        assert!(!verdict.forwards(Action::Allow));
+        assert!(verdict.forwards(Action::Deny));
+        assert_eq!(
+            verdict.headers(),
+            vec![(FASTLY_IP_HEADER, "true".to_owned())]
+        );
+
+        let mapped = Verdict::judge("::ffff:151.101.1.1".parse().ok(), &fastly, &providers);
+        assert_eq!(mapped.is_fastly_ip, Some(true));
+        let mapped = Verdict::judge("::ffff:104.16.1.1".parse().ok(), &fastly, &providers);
+        assert_eq!(mapped.is_fastly_ip, Some(false));
+        assert_eq!(
+            mapped.headers()[1..],
+            [
+                (PROVIDER_HEADER, "cloudflare".to_owned()),
+                (PREFIX_HEADER, "104.16.0.0/13".to_owned()),
+            ]
+        );
+
+        let verdict = Verdict::judge(None, &fastly, &providers);
+        assert!(!verdict.forwards(Action::Allow));
+        assert!(verdict.forwards(Action::Annotate));
+        assert_eq!(
+            verdict.headers(),
+            vec![(FASTLY_IP_HEADER, "unknown".to_owned())]
+        );
+
+        let rejection = verdict.rejection();
+        assert_eq!(rejection.status, 403);
+        assert_eq!(rejection.body, "The address of the request is unknown");
+    }
+}
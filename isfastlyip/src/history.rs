@@ -0,0 +1,257 @@
+use anyhow::{Context, Result};
+use hmac_sha256::Hash;
+use std::collections::HashMap;
+
+use crate::list::IpRanges;
+use crate::webhook::{constant_time_eq, hex};
+
+/// The KV store the history of the IP list is kept in.
+pub const HISTORY_STORE: &str = "isfastlyip_history";
+
+const INDEX_KEY: &str = "versions";
+
+/// How stale `last_seen` may get before it is written again, so that every
+/// refresh does not turn into a store write.
+pub const LAST_SEEN_RESOLUTION: i64 = 60 * 60;
+
+/// The config key holding the token `POST /v1/refresh` must be called with.
+pub const REFRESH_TOKEN_KEY: &str = "refresh_token";
+
+/// Whether an `Authorization` header carries the refresh token as a bearer
+/// token. Refreshing is disabled until a token is configured.
+pub fn authorizes_refresh(token: Option<&str>, authorization: Option<&str>) -> bool {
+    let given = authorization.and_then(|value| value.trim().strip_prefix("Bearer "));
+    match (token.filter(|token| !token.is_empty()), given) {
+        (Some(token), Some(given)) => constant_time_eq(token.as_bytes(), given.trim().as_bytes()),
+        _ => false,
+    }
+}
+
+/// The SHA-256 of a normalized list, in hex, telling its versions apart.
+pub fn content_hash(ranges: &IpRanges) -> String {
+    let json = serde_json::to_vec(ranges).expect("networks always serialize");
+    hex(&Hash::hash(&json))
+}
+
+/// A key-value store holding the history.
+pub trait SnapshotStore {
+    fn get(&self, key: &str) -> Result<Option<String>>;
+    fn put(&mut self, key: &str, value: String) -> Result<()>;
+}
+
+/// A store kept in memory, standing in for the KV store in tests.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    entries: HashMap<String, String>,
+}
+
+impl SnapshotStore for MemoryStore {
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.entries.get(key).cloned())
+    }
+
+    fn put(&mut self, key: &str, value: String) -> Result<()> {
+        self.entries.insert(key.to_owned(), value);
+        Ok(())
+    }
+}
+
+/// One distinct version of the list, and when it was seen, in Unix seconds.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+pub struct Version {
+    /// The [`content_hash`] of the version, which identifies it.
+    pub hash: String,
+    pub first_seen: i64,
+    pub last_seen: i64,
+}
+
+/// The shortest hash prefix a version can be looked up by.
+pub const MIN_HASH_PREFIX: usize = 7;
+
+fn version_key(hash: &str) -> String {
+    format!("version/{}", hash)
+}
+
+/// The versions of the list seen over time.
+///
+/// The index of versions is stored under one key, ordered by `first_seen`,
+/// and the normalized networks of each version under a key named after its
+/// hash. A version is taken to be valid from its `first_seen` until the next
+/// one was first seen.
+///
+/// The store has no conditional writes, so POPs recording at the same time
+/// can overwrite each other's index. Keying versions by content makes that
+/// harmless when they record the same new version, which is what happens
+/// when the list changes: they write the same networks under the same key
+/// and the same entry to the index.
+#[derive(Debug)]
+pub struct History<S> {
+    store: S,
+}
+
+impl<S: SnapshotStore> History<S> {
+    pub fn new(store: S) -> Self {
+        History { store }
+    }
+
+    pub fn versions(&self) -> Result<Vec<Version>> {
+        match self.store.get(INDEX_KEY)? {
+            Some(index) => serde_json::from_str(&index).context("Corrupt history index"),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn ranges(&self, version: &Version) -> Result<IpRanges> {
+        let body = self
+            .store
+            .get(&version_key(&version.hash))?
+            .with_context(|| format!("Version {} of the IP list is missing", version.hash))?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// The latest version recorded.
+    pub fn latest(&self) -> Result<Option<(Version, IpRanges)>> {
+        match self.versions()?.pop() {
+            Some(version) => {
+                let ranges = self.ranges(&version)?;
+                Ok(Some((version, ranges)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// The latest version whose hash starts with `prefix`.
+    pub fn get(&self, prefix: &str) -> Result<Option<(Version, IpRanges)>> {
+        match self
+            .versions()?
+            .into_iter()
+            .rev()
+            .find(|version| version.hash.starts_with(prefix))
+        {
+            Some(version) => {
+                let ranges = self.ranges(&version)?;
+                Ok(Some((version, ranges)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Records that `ranges`, which must be normalized, were fetched at `now`.
+    /// Returns whether they are a new version.
+    pub fn record(&mut self, ranges: &IpRanges, now: i64) -> Result<bool> {
+        let hash = content_hash(ranges);
+        let mut versions = self.versions()?;
+        if let Some(latest) = versions.last() {
+            if latest.hash == hash {
+                if now - latest.last_seen >= LAST_SEEN_RESOLUTION {
+                    versions.last_mut().unwrap().last_seen = now;
+                    self.store
+                        .put(INDEX_KEY, serde_json::to_string(&versions)?)?;
+                }
+                return Ok(false);
+            }
+        }
+
+        // The networks go in first, so that the index never points to a
+        // version that is not stored.
+        self.store
+            .put(&version_key(&hash), serde_json::to_string(ranges)?)?;
+        let version = Version {
+            hash,
+            first_seen: now,
+            last_seen: now,
+        };
+        versions.push(version);
+        self.store
+            .put(INDEX_KEY, serde_json::to_string(&versions)?)?;
+        Ok(true)
+    }
+
+    /// The version that was valid at `time`, or `None` when `time` predates
+    /// the history.
+    pub fn at(&self, time: i64) -> Result<Option<(Version, IpRanges)>> {
+        let versions = self.versions()?;
+        match versions
+            .iter()
+            .rev()
+            .find(|version| version.first_seen <= time)
+        {
+            Some(version) => Ok(Some((version.clone(), self.ranges(version)?))),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(nets: &[&str]) -> IpRanges {
+        let mut ranges = IpRanges::default();
+        for net in nets {
+            ranges.push(net.parse().unwrap());
+        }
+        ranges
+    }
+
+    #[test]
+    fn records_distinct_versions() {
+        let old = ranges(&["151.101.0.0/16"]);
+        let new = ranges(&["151.101.0.0/16", "2a04:4e42::/32"]);
+        let mut history = History::new(MemoryStore::default());
+
+        assert!(history.record(&old, 1000).unwrap());
+        assert!(!history.record(&old, 1000 + LAST_SEEN_RESOLUTION).unwrap());
+        assert!(history.record(&new, 9000).unwrap());
+        assert!(!history.record(&new, 9001).unwrap());
+
+        assert_eq!(
+            history.versions().unwrap(),
+            vec![
+                Version {
+                    hash: content_hash(&old),
+                    first_seen: 1000,
+                    last_seen: 1000 + LAST_SEEN_RESOLUTION,
+                },
+                Version {
+                    hash: content_hash(&new),
+                    first_seen: 9000,
+                    last_seen: 9000,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn lookup_at_time() {
+        let old = ranges(&["151.101.0.0/16"]);
+        let new = ranges(&["199.232.0.0/16"]);
+        let mut history = History::new(MemoryStore::default());
+        history.record(&old, 1000).unwrap();
+        history.record(&new, 5000).unwrap();
+
+        assert!(history.at(999).unwrap().is_none());
+        assert_eq!(history.latest().unwrap().unwrap().1, new);
+        let hash = content_hash(&new);
+        assert_eq!(
+            history.get(&hash[..MIN_HASH_PREFIX]).unwrap().unwrap().1,
+            new
+        );
+        assert!(history.get("0000000").unwrap().is_none());
+        assert_eq!(history.at(1000).unwrap().unwrap().1, old);
+        assert_eq!(history.at(4999).unwrap().unwrap().1, old);
+        let (version, at) = history.at(1 << 40).unwrap().unwrap();
+        assert_eq!(version.hash, hash);
+        assert_eq!(at, new);
+    }
+
+    #[test]
+    fn refresh_token() {
+        assert!(authorizes_refresh(Some("s3cret"), Some("Bearer s3cret")));
+        assert!(!authorizes_refresh(Some("s3cret"), Some("Bearer other")));
+        assert!(!authorizes_refresh(Some("s3cret"), Some("s3cret")));
+        assert!(!authorizes_refresh(Some("s3cret"), None));
+        assert!(!authorizes_refresh(None, Some("Bearer ")));
+        assert!(!authorizes_refresh(Some(""), Some("Bearer ")));
+    }
+}
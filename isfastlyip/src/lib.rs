@@ -0,0 +1,34 @@
+//! Core of the isfastlyip service: the Fastly IP list model, request path
+//! parsing, address matching and response rendering.
+//!
+//! Nothing in this crate depends on the `fastly` crate, so it can be tested
+//! natively with a plain `cargo test`. The Compute@Edge entrypoint in the
+//! root package only fetches the list and adapts [`Reply`] into a response.
+
+pub mod addr;
+pub mod batch;
+pub mod cache;
+pub mod coverage;
+pub mod diff;
+pub mod embedded;
+pub mod export;
+pub mod forwarded;
+pub mod gatekeeper;
+pub mod history;
+pub mod list;
+pub mod matcher;
+pub mod normalize;
+pub mod provider;
+pub mod render;
+pub mod response;
+pub mod router;
+pub mod service;
+pub mod snapshot;
+pub mod timestamp;
+pub mod trie;
+pub mod validate;
+pub mod webhook;
+
+pub use list::{FastlyIpList, IpRanges, ListSource};
+pub use matcher::IpMatcher;
+pub use response::{AddressFamily, IpCheckResult, Reply};
@@ -0,0 +1,142 @@
+use anyhow::Result;
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+
+use crate::matcher::IpMatcher;
+use crate::timestamp;
+
+/// The networks published by a provider, parsed and split by family.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct IpRanges {
+    pub ipv4: Vec<Ipv4Net>,
+    pub ipv6: Vec<Ipv6Net>,
+}
+
+impl IpRanges {
+    pub fn push(&mut self, net: IpNet) {
+        match net {
+            IpNet::V4(net) => self.ipv4.push(net),
+            IpNet::V6(net) => self.ipv6.push(net),
+        }
+    }
+
+    pub fn extend(&mut self, other: IpRanges) {
+        self.ipv4.extend(other.ipv4);
+        self.ipv6.extend(other.ipv6);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ipv4.is_empty() && self.ipv6.is_empty()
+    }
+
+    pub fn matcher(&self) -> IpMatcher {
+        IpMatcher::new(self.ipv4.iter().cloned(), self.ipv6.iter().cloned())
+    }
+}
+
+/// Where the list used to answer a request came from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ListSource {
+    /// Fetched from the Fastly API.
+    Live,
+    /// The snapshot compiled into the binary, taken on the given date.
+    Snapshot(&'static str),
+    /// A version from the history, seen between the given Unix times.
+    History { first_seen: i64, last_seen: i64 },
+}
+
+impl ListSource {
+    /// Response headers telling clients which list answered them.
+    pub fn headers(self) -> Vec<(&'static str, String)> {
+        match self {
+            ListSource::Live => vec![("x-ip-list-source", "live".to_owned())],
+            ListSource::Snapshot(date) => vec![
+                ("x-ip-list-source", "snapshot".to_owned()),
+                ("x-ip-list-snapshot-date", date.to_owned()),
+            ],
+            ListSource::History {
+                first_seen,
+                last_seen,
+            } => vec![
+                ("x-ip-list-source", "history".to_owned()),
+                (
+                    "x-ip-list-first-seen",
+                    timestamp::format_rfc3339(first_seen),
+                ),
+                ("x-ip-list-last-seen", timestamp::format_rfc3339(last_seen)),
+            ],
+        }
+    }
+}
+
+/// The body of `https://api.fastly.com/public-ip-list`.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct FastlyIpList {
+    pub addresses: Vec<String>,
+    pub ipv6_addresses: Vec<String>,
+}
+
+impl FastlyIpList {
+    pub fn from_json(body: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(body)?)
+    }
+
+    /// Lists `ranges` in the published format.
+    pub fn from_ranges(ranges: &IpRanges) -> Self {
+        FastlyIpList {
+            addresses: ranges.ipv4.iter().map(ToString::to_string).collect(),
+            ipv6_addresses: ranges.ipv6.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    /// Parses every network in the list.
+    pub fn ranges(&self) -> Result<IpRanges> {
+        let ipv4 = self
+            .addresses
+            .iter()
+            .map(|net| net.parse::<Ipv4Net>())
+            .collect::<Result<Vec<_>, _>>()?;
+        let ipv6 = self
+            .ipv6_addresses
+            .iter()
+            .map(|net| net.parse::<Ipv6Net>())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(IpRanges { ipv4, ipv6 })
+    }
+
+    /// Parses every network in the list and compiles them into a matcher.
+    pub fn matcher(&self) -> Result<IpMatcher> {
+        Ok(self.ranges()?.matcher())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_list() {
+        let list = FastlyIpList::from_json(
+            br#"{"addresses":["151.101.0.0/16"],"ipv6_addresses":["2a04:4e40::/32"]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(list.addresses, vec!["151.101.0.0/16"]);
+        assert_eq!(list.ipv6_addresses, vec!["2a04:4e40::/32"]);
+        assert!(list.matcher().is_ok());
+        assert_eq!(
+            FastlyIpList::from_ranges(&list.ranges().unwrap()).addresses,
+            list.addresses
+        );
+    }
+
+    #[test]
+    fn invalid_network() {
+        let list = FastlyIpList {
+            addresses: vec!["151.101.0.0/33".to_owned()],
+            ipv6_addresses: vec![],
+        };
+
+        assert!(list.matcher().is_err());
+    }
+}
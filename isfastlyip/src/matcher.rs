@@ -0,0 +1,131 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+
+use crate::trie::PrefixTrie;
+
+fn ipv4_key(addr: Ipv4Addr) -> u128 {
+    u128::from(u32::from(addr)) << 96
+}
+
+fn ipv6_key(addr: Ipv6Addr) -> u128 {
+    u128::from(addr)
+}
+
+/// Lookup engine compiled once from a list of IPv4 and IPv6 networks.
+pub struct IpMatcher {
+    v4: PrefixTrie<Ipv4Net>,
+    v6: PrefixTrie<Ipv6Net>,
+}
+
+impl IpMatcher {
+    pub fn new<I4, I6>(ipv4_nets: I4, ipv6_nets: I6) -> Self
+    where
+        I4: IntoIterator<Item = Ipv4Net>,
+        I6: IntoIterator<Item = Ipv6Net>,
+    {
+        let mut v4 = PrefixTrie::new();
+        for net in ipv4_nets {
+            let net = net.trunc();
+            v4.insert(ipv4_key(net.network()), net.prefix_len(), net);
+        }
+
+        let mut v6 = PrefixTrie::new();
+        for net in ipv6_nets {
+            let net = net.trunc();
+            v6.insert(ipv6_key(net.network()), net.prefix_len(), net);
+        }
+
+        IpMatcher { v4, v6 }
+    }
+
+    /// Returns the most specific network containing `addr`, so that when
+    /// published prefixes overlap the narrowest one is reported.
+    pub fn lookup(&self, addr: IpAddr) -> Option<IpNet> {
+        match addr {
+            IpAddr::V4(ipv4) => self.lookup_v4(ipv4).map(|net| IpNet::V4(*net)),
+            IpAddr::V6(ipv6) => self.lookup_v6(ipv6).map(|net| IpNet::V6(*net)),
+        }
+    }
+
+    /// Returns every network in the list that overlaps `net`.
+    pub fn intersecting(&self, net: IpNet) -> Vec<IpNet> {
+        match net.trunc() {
+            IpNet::V4(net) => self
+                .v4
+                .intersecting(ipv4_key(net.network()), net.prefix_len())
+                .into_iter()
+                .map(|net| IpNet::V4(*net))
+                .collect(),
+            IpNet::V6(net) => self
+                .v6
+                .intersecting(ipv6_key(net.network()), net.prefix_len())
+                .into_iter()
+                .map(|net| IpNet::V6(*net))
+                .collect(),
+        }
+    }
+
+    /// Returns the most specific IPv4 network containing `addr`.
+    pub fn lookup_v4(&self, addr: Ipv4Addr) -> Option<&Ipv4Net> {
+        self.v4.longest_match(ipv4_key(addr))
+    }
+
+    /// Returns the most specific IPv6 network containing `addr`.
+    pub fn lookup_v6(&self, addr: Ipv6Addr) -> Option<&Ipv6Net> {
+        self.v6.longest_match(ipv6_key(addr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ipv4contains() {
+        let net: Ipv4Net = "151.101.0.0/16".parse().unwrap();
+        let ip_yes: Ipv4Addr = "151.101.0.1".parse().unwrap();
+        let ip_no: Ipv4Addr = "151.102.0.0".parse().unwrap();
+
+        assert!(net.contains(&ip_yes));
+        assert!(!net.contains(&ip_no));
+    }
+
+    #[test]
+    fn ipv6contains() {
+        let net: Ipv6Net = "2a04:4e40::/32".parse().unwrap();
+        let ip_yes: Ipv6Addr = "2a04:4e40:7f6:9100:f8c9:7bf6:e4f5:c5f3".parse().unwrap();
+        let ip_no: Ipv6Addr = "240d:1a:7f6:9100:f8c9:7bf6:e4f5:c5f3".parse().unwrap();
+
+        assert!(net.contains(&ip_yes));
+        assert!(!net.contains(&ip_no));
+    }
+
+    #[test]
+    fn matcher_lookup() {
+        let matcher = IpMatcher::new(
+            vec![
+                "151.101.0.0/16".parse().unwrap(),
+                "151.101.64.0/22".parse().unwrap(),
+            ],
+            vec!["2a04:4e40::/32".parse().unwrap()],
+        );
+
+        let hit: Ipv4Net = "151.101.64.0/22".parse().unwrap();
+        assert_eq!(
+            matcher.lookup_v4("151.101.65.1".parse().unwrap()),
+            Some(&hit)
+        );
+        assert!(matcher.lookup_v4("151.102.0.0".parse().unwrap()).is_none());
+        assert!(matcher
+            .lookup_v6("2a04:4e40:7f6:9100:f8c9:7bf6:e4f5:c5f3".parse().unwrap())
+            .is_some());
+        assert_eq!(
+            matcher.lookup("151.101.1.1".parse().unwrap()),
+            Some("151.101.0.0/16".parse().unwrap())
+        );
+        assert!(matcher
+            .lookup_v6("240d:1a:7f6:9100:f8c9:7bf6:e4f5:c5f3".parse().unwrap())
+            .is_none());
+    }
+}
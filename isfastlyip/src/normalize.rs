@@ -0,0 +1,175 @@
+use ipnet::IpNet;
+
+use crate::coverage::net_bounds;
+use crate::list::IpRanges;
+
+/// A network published with host bits set.
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct Truncation {
+    pub original: IpNet,
+    pub canonical: IpNet,
+}
+
+/// Published networks that were replaced by a single covering one.
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct Merge {
+    pub into: IpNet,
+    pub from: Vec<IpNet>,
+}
+
+/// What normalizing a list changed.
+#[derive(serde::Serialize, Clone, Debug, Default, PartialEq)]
+pub struct NormalizationReport {
+    pub input_networks: usize,
+    pub output_networks: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub truncated: Vec<Truncation>,
+    /// Duplicate, overlapping and adjacent networks that were aggregated.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub merged: Vec<Merge>,
+}
+
+/// A list reduced to its minimal canonical set of networks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Normalized {
+    pub ranges: IpRanges,
+    pub report: NormalizationReport,
+}
+
+/// Clears host bits and aggregates the networks of one family.
+fn normalize_family(nets: Vec<IpNet>, report: &mut NormalizationReport) -> Vec<IpNet> {
+    let mut canonical = Vec::with_capacity(nets.len());
+    for net in nets {
+        let trunc = net.trunc();
+        if trunc != net {
+            report.truncated.push(Truncation {
+                original: net,
+                canonical: trunc,
+            });
+        }
+        canonical.push(trunc);
+    }
+
+    let aggregated = IpNet::aggregate(&canonical);
+
+    // The aggregated networks are sorted and disjoint, so every input
+    // belongs to the last one starting at or before it.
+    canonical.sort_by_key(net_bounds);
+    let mut sources = vec![Vec::new(); aggregated.len()];
+    for net in canonical {
+        let start = net_bounds(&net).0;
+        let index = match aggregated.binary_search_by_key(&start, |agg| net_bounds(agg).0) {
+            Ok(index) => index,
+            Err(index) => index - 1,
+        };
+        sources[index].push(net);
+    }
+    for (into, from) in aggregated.iter().zip(sources) {
+        if from.as_slice() != [*into] {
+            report.merged.push(Merge { into: *into, from });
+        }
+    }
+
+    aggregated
+}
+
+/// Reduces `ranges` to the minimal set of canonical networks covering the
+/// same addresses, reporting every network that was rewritten.
+pub fn normalize(ranges: IpRanges) -> Normalized {
+    let mut report = NormalizationReport {
+        input_networks: ranges.ipv4.len() + ranges.ipv6.len(),
+        ..NormalizationReport::default()
+    };
+
+    let mut normalized = IpRanges::default();
+    let ipv4 = ranges.ipv4.into_iter().map(IpNet::V4).collect();
+    let ipv6 = ranges.ipv6.into_iter().map(IpNet::V6).collect();
+    for net in normalize_family(ipv4, &mut report) {
+        normalized.push(net);
+    }
+    for net in normalize_family(ipv6, &mut report) {
+        normalized.push(net);
+    }
+
+    report.output_networks = normalized.ipv4.len() + normalized.ipv6.len();
+    Normalized {
+        ranges: normalized,
+        report,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nets(nets: &[&str]) -> Vec<IpNet> {
+        nets.iter().map(|net| net.parse().unwrap()).collect()
+    }
+
+    fn ranges(list: &[&str]) -> IpRanges {
+        let mut ranges = IpRanges::default();
+        for net in nets(list) {
+            ranges.push(net);
+        }
+        ranges
+    }
+
+    #[test]
+    fn aggregates_and_truncates() {
+        let normalized = normalize(ranges(&[
+            "151.101.0.0/16",
+            "151.101.64.0/22",
+            "104.156.88.0/21",
+            "104.156.80.0/21",
+            "23.235.33.7/20",
+            "2a04:4e42::/32",
+            "2a04:4e42::/32",
+        ]));
+
+        assert_eq!(
+            normalized.ranges,
+            ranges(&[
+                "23.235.32.0/20",
+                "104.156.80.0/20",
+                "151.101.0.0/16",
+                "2a04:4e42::/32",
+            ])
+        );
+        assert_eq!(normalized.report.input_networks, 7);
+        assert_eq!(normalized.report.output_networks, 4);
+        assert_eq!(
+            normalized.report.truncated,
+            vec![Truncation {
+                original: "23.235.33.7/20".parse().unwrap(),
+                canonical: "23.235.32.0/20".parse().unwrap(),
+            }]
+        );
+        assert_eq!(
+            normalized.report.merged,
+            vec![
+                Merge {
+                    into: "104.156.80.0/20".parse().unwrap(),
+                    from: nets(&["104.156.80.0/21", "104.156.88.0/21"]),
+                },
+                Merge {
+                    into: "151.101.0.0/16".parse().unwrap(),
+                    from: nets(&["151.101.0.0/16", "151.101.64.0/22"]),
+                },
+                Merge {
+                    into: "2a04:4e42::/32".parse().unwrap(),
+                    from: nets(&["2a04:4e42::/32", "2a04:4e42::/32"]),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn canonical_list_is_unchanged() {
+        let list = ranges(&["23.235.32.0/20", "151.101.0.0/16", "2a04:4e40::/32"]);
+        let normalized = normalize(list.clone());
+
+        assert_eq!(normalized.ranges, list);
+        assert!(normalized.report.merged.is_empty());
+        assert!(normalized.report.truncated.is_empty());
+    }
+}
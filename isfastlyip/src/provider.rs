@@ -0,0 +1,300 @@
+use anyhow::{Context, Result};
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+use crate::list::{FastlyIpList, IpRanges};
+use crate::matcher::IpMatcher;
+use crate::response::AddressFamily;
+
+/// The formats in which providers publish their address ranges.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ListFormat {
+    /// Fastly's `public-ip-list` JSON.
+    Fastly,
+    /// One network per line, as in Cloudflare's `ips-v4` and `ips-v6`.
+    PlainText,
+    /// AWS `ip-ranges.json`.
+    Aws,
+    /// Google Cloud `cloud.json`.
+    Gcp,
+    /// Azure service tags JSON.
+    Azure,
+}
+
+#[derive(serde::Deserialize)]
+struct AwsIpRanges {
+    prefixes: Vec<AwsPrefix>,
+    ipv6_prefixes: Vec<AwsIpv6Prefix>,
+}
+
+#[derive(serde::Deserialize)]
+struct AwsPrefix {
+    ip_prefix: IpNet,
+}
+
+#[derive(serde::Deserialize)]
+struct AwsIpv6Prefix {
+    ipv6_prefix: IpNet,
+}
+
+#[derive(serde::Deserialize)]
+struct GcpIpRanges {
+    prefixes: Vec<GcpPrefix>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GcpPrefix {
+    ipv4_prefix: Option<IpNet>,
+    ipv6_prefix: Option<IpNet>,
+}
+
+#[derive(serde::Deserialize)]
+struct AzureServiceTags {
+    values: Vec<AzureServiceTag>,
+}
+
+#[derive(serde::Deserialize)]
+struct AzureServiceTag {
+    properties: AzureServiceTagProperties,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AzureServiceTagProperties {
+    address_prefixes: Vec<IpNet>,
+}
+
+impl ListFormat {
+    /// Parses one published document into address ranges.
+    pub fn parse(self, body: &[u8]) -> Result<IpRanges> {
+        let mut ranges = IpRanges::default();
+        match self {
+            ListFormat::Fastly => return FastlyIpList::from_json(body)?.ranges(),
+            ListFormat::PlainText => {
+                let body = std::str::from_utf8(body)?;
+                for line in body.lines().map(str::trim) {
+                    if line.is_empty() || line.starts_with('#') {
+                        continue;
+                    }
+                    let net = line
+                        .parse::<IpNet>()
+                        .with_context(|| format!("Invalid network {:?}", line))?;
+                    ranges.push(net);
+                }
+            }
+            ListFormat::Aws => {
+                let list: AwsIpRanges = serde_json::from_slice(body)?;
+                for prefix in list.prefixes {
+                    ranges.push(prefix.ip_prefix);
+                }
+                for prefix in list.ipv6_prefixes {
+                    ranges.push(prefix.ipv6_prefix);
+                }
+            }
+            ListFormat::Gcp => {
+                let list: GcpIpRanges = serde_json::from_slice(body)?;
+                for prefix in list.prefixes {
+                    let net = prefix.ipv4_prefix.or(prefix.ipv6_prefix);
+                    ranges.push(net.context("Prefix without ipv4Prefix or ipv6Prefix")?);
+                }
+            }
+            ListFormat::Azure => {
+                let list: AzureServiceTags = serde_json::from_slice(body)?;
+                for tag in list.values {
+                    for net in tag.properties.address_prefixes {
+                        ranges.push(net);
+                    }
+                }
+            }
+        }
+        Ok(ranges)
+    }
+}
+
+/// A document a provider publishes its ranges in.
+#[derive(Debug)]
+pub struct Source {
+    pub host: &'static str,
+    pub path: &'static str,
+}
+
+/// A CDN or cloud whose published address ranges can be checked.
+#[derive(Debug)]
+pub struct Provider {
+    pub name: &'static str,
+    /// The Compute@Edge backend serving `sources`.
+    pub backend: &'static str,
+    pub sources: &'static [Source],
+    pub format: ListFormat,
+    /// How long, in seconds, a fetched document may be cached.
+    pub ttl: u32,
+}
+
+const DAY: u32 = 60 * 60 * 24;
+
+pub const FASTLY: Provider = Provider {
+    name: "fastly",
+    backend: "fastlyapi",
+    sources: &[Source {
+        host: "api.fastly.com",
+        path: "/public-ip-list",
+    }],
+    format: ListFormat::Fastly,
+    ttl: 7 * DAY,
+};
+
+/// Every provider the service knows about.
+///
+/// Azure only publishes its service tags under a weekly changing file name
+/// and Akamai only as a download from its control center, so both are read
+/// from a mirror behind their backend rather than from the origin directly.
+pub const PROVIDERS: &[Provider] = &[
+    FASTLY,
+    Provider {
+        name: "cloudflare",
+        backend: "cloudflare",
+        sources: &[
+            Source {
+                host: "www.cloudflare.com",
+                path: "/ips-v4",
+            },
+            Source {
+                host: "www.cloudflare.com",
+                path: "/ips-v6",
+            },
+        ],
+        format: ListFormat::PlainText,
+        ttl: DAY,
+    },
+    Provider {
+        name: "aws",
+        backend: "aws",
+        sources: &[Source {
+            host: "ip-ranges.amazonaws.com",
+            path: "/ip-ranges.json",
+        }],
+        format: ListFormat::Aws,
+        ttl: DAY,
+    },
+    Provider {
+        name: "gcp",
+        backend: "gcp",
+        sources: &[Source {
+            host: "www.gstatic.com",
+            path: "/ipranges/cloud.json",
+        }],
+        format: ListFormat::Gcp,
+        ttl: DAY,
+    },
+    Provider {
+        name: "azure",
+        backend: "azure",
+        sources: &[Source {
+            host: "azure-service-tags",
+            path: "/ServiceTags_Public.json",
+        }],
+        format: ListFormat::Azure,
+        ttl: DAY,
+    },
+    Provider {
+        name: "akamai",
+        backend: "akamai",
+        sources: &[
+            Source {
+                host: "akamai-ip-list",
+                path: "/akamai_ipv4_CIDRs.txt",
+            },
+            Source {
+                host: "akamai-ip-list",
+                path: "/akamai_ipv6_CIDRs.txt",
+            },
+        ],
+        format: ListFormat::PlainText,
+        ttl: DAY,
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Provider> {
+    PROVIDERS.iter().find(|provider| provider.name == name)
+}
+
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct ProviderMatch {
+    pub provider: &'static str,
+    pub matched_prefix: IpNet,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct OwnerResult {
+    pub ip: IpAddr,
+    pub family: AddressFamily,
+    /// Every provider whose ranges contain the address.
+    pub providers: Vec<ProviderMatch>,
+    /// Providers whose ranges could not be fetched or parsed.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unavailable: Vec<&'static str>,
+}
+
+/// Checks which of the providers own `addr`.
+pub fn find_owners(
+    addr: IpAddr,
+    matchers: &[(&'static Provider, IpMatcher)],
+    unavailable: Vec<&'static str>,
+) -> OwnerResult {
+    let providers = matchers
+        .iter()
+        .filter_map(|(provider, matcher)| {
+            matcher.lookup(addr).map(|matched_prefix| ProviderMatch {
+                provider: provider.name,
+                matched_prefix,
+            })
+        })
+        .collect();
+
+    OwnerResult {
+        ip: addr,
+        family: AddressFamily::of(addr),
+        providers,
+        unavailable,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_text() {
+        let ranges = ListFormat::PlainText
+            .parse(b"# comment\n173.245.48.0/20\n\n2400:cb00::/32\n")
+            .unwrap();
+
+        assert_eq!(ranges.ipv4, vec!["173.245.48.0/20".parse().unwrap()]);
+        assert_eq!(ranges.ipv6, vec!["2400:cb00::/32".parse().unwrap()]);
+        assert!(ListFormat::PlainText.parse(b"173.245.48.0/33").is_err());
+    }
+
+    #[test]
+    fn owners() {
+        let fastly = ListFormat::PlainText.parse(b"151.101.0.0/16").unwrap();
+        let cloudflare = ListFormat::PlainText.parse(b"104.16.0.0/13").unwrap();
+        let matchers = vec![
+            (find("fastly").unwrap(), fastly.matcher()),
+            (find("cloudflare").unwrap(), cloudflare.matcher()),
+        ];
+
+        let result = find_owners("104.16.1.1".parse().unwrap(), &matchers, vec!["aws"]);
+        assert_eq!(
+            result.providers,
+            vec![ProviderMatch {
+                provider: "cloudflare",
+                matched_prefix: "104.16.0.0/13".parse().unwrap(),
+            }]
+        );
+        assert_eq!(result.unavailable, vec!["aws"]);
+        assert!(find_owners("8.8.8.8".parse().unwrap(), &matchers, vec![])
+            .providers
+            .is_empty());
+    }
+}
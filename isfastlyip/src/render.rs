@@ -0,0 +1,361 @@
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+use crate::addr;
+use crate::batch::BatchItem;
+use crate::coverage::{Coverage, NetCheckResult, RangeCheckResult};
+use crate::response::IpCheckResult;
+
+/// The formats a lookup can be rendered in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    /// `true` or `false`, one line per queried item.
+    Text,
+    /// A header row named after the JSON fields, then one row per item.
+    Csv,
+    /// One JSON object per line.
+    Ndjson,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Format::Json),
+            "text" | "txt" => Some(Format::Text),
+            "csv" => Some(Format::Csv),
+            "ndjson" => Some(Format::Ndjson),
+            _ => None,
+        }
+    }
+
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type {
+            "application/json" | "application/*" | "*/*" => Some(Format::Json),
+            "text/plain" => Some(Format::Text),
+            "text/csv" => Some(Format::Csv),
+            "application/x-ndjson" | "application/ndjson" => Some(Format::Ndjson),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Text => "text/plain; charset=utf-8",
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    /// Picks the format from a `format` query parameter, or else the
+    /// supported media type the `Accept` header prefers. JSON is the default
+    /// when neither names a supported format.
+    pub fn negotiate(query: Option<&str>, accept: Option<&str>) -> Result<Self> {
+        if let Some(name) = addr::query_param(query, "format") {
+            return match Format::from_name(&name) {
+                Some(format) => Ok(format),
+                None => bail!("Unsupported format {:?}", name),
+            };
+        }
+
+        let mut best: Option<(Format, f32)> = None;
+        for range in accept.unwrap_or_default().split(',') {
+            let mut params = range.split(';').map(str::trim);
+            let media_type = params.next().unwrap_or_default().to_ascii_lowercase();
+            let quality = params
+                .filter_map(|param| param.strip_prefix("q="))
+                .filter_map(|q| q.parse::<f32>().ok())
+                .next()
+                .unwrap_or(1.0);
+            let format = match Format::from_media_type(&media_type) {
+                Some(format) if quality > 0.0 => format,
+                _ => continue,
+            };
+            match best {
+                Some((_, best_quality)) if best_quality >= quality => {}
+                _ => best = Some((format, quality)),
+            }
+        }
+        Ok(best.map_or(Format::Json, |(format, _)| format))
+    }
+}
+
+/// A result that can be rendered in every [`Format`].
+pub trait Render: Serialize {
+    /// The plain-text rendering; one `true` or `false` line per item for
+    /// lookups.
+    fn text(&self) -> String;
+
+    /// The CSV header, named like the JSON fields.
+    fn csv_header(&self) -> &'static [&'static str];
+
+    /// One CSV row per item, in the order of the header.
+    fn csv_rows(&self) -> Vec<Vec<String>>;
+
+    /// One JSON object per line.
+    fn ndjson(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)? + "\n")
+    }
+
+    fn render(&self, format: Format) -> Result<String> {
+        Ok(match format {
+            Format::Json => serde_json::to_string(self)?,
+            Format::Text => self.text(),
+            Format::Csv => to_csv(self.csv_header(), self.csv_rows()),
+            Format::Ndjson => self.ndjson()?,
+        })
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+fn to_csv(header: &[&str], rows: Vec<Vec<String>>) -> String {
+    let mut csv = header.join(",") + "\r\n";
+    for row in rows {
+        let fields: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+const CHECK_COLUMNS: &[&str] = &[
+    "query",
+    "is_fastly_ip",
+    "ip",
+    "family",
+    "matched_prefix",
+    "embedded_ipv4",
+    "error",
+];
+
+fn check_row(query: &str, result: Option<&IpCheckResult>, error: Option<&str>) -> Vec<String> {
+    let mut row = vec![query.to_owned()];
+    match result {
+        Some(result) => row.extend(vec![
+            result.is_fastly_ip.to_string(),
+            result.ip.to_string(),
+            result.family.as_str().to_owned(),
+            optional(result.matched_prefix),
+            optional(result.embedded_ipv4.as_ref().map(|embedded| embedded.ip)),
+        ]),
+        None => row.extend(vec![String::new(); 5]),
+    }
+    row.push(optional(error));
+    row
+}
+
+impl Render for IpCheckResult {
+    fn text(&self) -> String {
+        format!("{}\n", self.is_fastly_ip)
+    }
+
+    fn csv_header(&self) -> &'static [&'static str] {
+        CHECK_COLUMNS
+    }
+
+    fn csv_rows(&self) -> Vec<Vec<String>> {
+        vec![check_row(&self.ip.to_string(), Some(self), None)]
+    }
+}
+
+impl Render for BatchItem {
+    /// An entry that is not an address is rendered as `error`.
+    fn text(&self) -> String {
+        match &self.result {
+            Some(result) => format!("{}\n", result.is_fastly_ip),
+            None => "error\n".to_owned(),
+        }
+    }
+
+    fn csv_header(&self) -> &'static [&'static str] {
+        CHECK_COLUMNS
+    }
+
+    fn csv_rows(&self) -> Vec<Vec<String>> {
+        vec![check_row(
+            &self.query,
+            self.result.as_ref(),
+            self.error.as_deref(),
+        )]
+    }
+}
+
+impl Render for Vec<BatchItem> {
+    /// Entries that are not addresses are rendered as `error`, so that line
+    /// numbers keep matching the request.
+    fn text(&self) -> String {
+        self.iter().map(BatchItem::text).collect()
+    }
+
+    fn csv_header(&self) -> &'static [&'static str] {
+        CHECK_COLUMNS
+    }
+
+    fn csv_rows(&self) -> Vec<Vec<String>> {
+        self.iter().flat_map(BatchItem::csv_rows).collect()
+    }
+
+    fn ndjson(&self) -> Result<String> {
+        let mut ndjson = String::new();
+        for item in self {
+            ndjson.push_str(&item.ndjson()?);
+        }
+        Ok(ndjson)
+    }
+}
+
+impl Render for NetCheckResult {
+    /// `true` only when the whole network is listed.
+    fn text(&self) -> String {
+        format!("{}\n", self.coverage == Coverage::Covered)
+    }
+
+    fn csv_header(&self) -> &'static [&'static str] {
+        &["network", "family", "coverage", "intersecting_prefix"]
+    }
+
+    fn csv_rows(&self) -> Vec<Vec<String>> {
+        let row = |prefix: String| {
+            vec![
+                self.network.to_string(),
+                self.family.as_str().to_owned(),
+                self.coverage.as_str().to_owned(),
+                prefix,
+            ]
+        };
+        if self.intersecting_prefixes.is_empty() {
+            return vec![row(String::new())];
+        }
+        self.intersecting_prefixes
+            .iter()
+            .map(|prefix| row(prefix.to_string()))
+            .collect()
+    }
+}
+
+impl Render for RangeCheckResult {
+    /// `true` only when every address of the range is listed.
+    fn text(&self) -> String {
+        format!("{}\n", self.fastly_addresses == self.total_addresses)
+    }
+
+    fn csv_header(&self) -> &'static [&'static str] {
+        &["start", "end", "is_fastly_ip"]
+    }
+
+    /// The listed and unlisted sub-ranges, in address order.
+    fn csv_rows(&self) -> Vec<Vec<String>> {
+        let mut ranges: Vec<_> = self
+            .fastly_ranges
+            .iter()
+            .map(|range| (range, true))
+            .chain(self.other_ranges.iter().map(|range| (range, false)))
+            .collect();
+        ranges.sort_by_key(|(range, _)| range.start);
+        ranges
+            .into_iter()
+            .map(|(range, is_fastly_ip)| {
+                vec![
+                    range.start.to_string(),
+                    range.end.to_string(),
+                    is_fastly_ip.to_string(),
+                ]
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::IpMatcher;
+    use crate::{batch, coverage, service};
+
+    fn matcher() -> IpMatcher {
+        IpMatcher::new(vec!["151.101.0.0/16".parse().unwrap()], vec![])
+    }
+
+    #[test]
+    fn negotiation() {
+        let negotiate = |query, accept| Format::negotiate(query, accept).unwrap();
+
+        assert_eq!(negotiate(None, None), Format::Json);
+        assert_eq!(
+            negotiate(Some("format=csv"), Some("text/plain")),
+            Format::Csv
+        );
+        assert_eq!(negotiate(None, Some("text/plain")), Format::Text);
+        assert_eq!(
+            negotiate(None, Some("text/csv;q=0.5, application/x-ndjson")),
+            Format::Ndjson
+        );
+        assert_eq!(
+            negotiate(None, Some("text/html,application/xml;q=0.9,*/*;q=0.8")),
+            Format::Json
+        );
+        assert!(Format::negotiate(Some("format=xml"), None).is_err());
+    }
+
+    #[test]
+    fn batch_formats() {
+        let items = vec![
+            "151.101.0.1".to_owned(),
+            "8.8.8.8".to_owned(),
+            "a,b".to_owned(),
+        ];
+        let results = batch::check_batch(&items, &matcher());
+
+        assert_eq!(
+            results.render(Format::Text).unwrap(),
+            "true\nfalse\nerror\n"
+        );
+        assert_eq!(
+            results.render(Format::Csv).unwrap(),
+            concat!(
+                "query,is_fastly_ip,ip,family,matched_prefix,embedded_ipv4,error\r\n",
+                "151.101.0.1,true,151.101.0.1,ipv4,151.101.0.0/16,,\r\n",
+                "8.8.8.8,false,8.8.8.8,ipv4,,,\r\n",
+                "\"a,b\",,,,,,\"\"\"a,b\"\" is not a valid IPv4 or IPv6 address\"\r\n",
+            )
+        );
+        assert_eq!(results.render(Format::Ndjson).unwrap().lines().count(), 3);
+    }
+
+    #[test]
+    fn single_and_range_formats() {
+        let result = service::check_ip("151.101.0.1".parse().unwrap(), &matcher());
+        assert_eq!(result.render(Format::Text).unwrap(), "true\n");
+        assert_eq!(
+            result.render(Format::Ndjson).unwrap(),
+            serde_json::to_string(&result).unwrap() + "\n"
+        );
+
+        let result = coverage::check_range(
+            "151.100.255.0".parse().unwrap(),
+            "151.101.0.255".parse().unwrap(),
+            &matcher(),
+        )
+        .unwrap();
+        assert_eq!(result.render(Format::Text).unwrap(), "false\n");
+        assert_eq!(
+            result.render(Format::Csv).unwrap(),
+            concat!(
+                "start,end,is_fastly_ip\r\n",
+                "151.100.255.0,151.100.255.255,false\r\n",
+                "151.101.0.0,151.101.0.255,true\r\n",
+            )
+        );
+    }
+}
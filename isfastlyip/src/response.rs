@@ -0,0 +1,90 @@
+use anyhow::Result;
+use ipnet::IpNet;
+
+use crate::embedded::EmbeddedIpv4;
+use crate::render::{Format, Render};
+use serde::Serialize;
+use std::net::IpAddr;
+
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressFamily {
+    Ipv4,
+    Ipv6,
+}
+
+impl AddressFamily {
+    pub fn of(addr: IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(_) => AddressFamily::Ipv4,
+            IpAddr::V6(_) => AddressFamily::Ipv6,
+        }
+    }
+
+    /// The name the family is serialized as.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AddressFamily::Ipv4 => "ipv4",
+            AddressFamily::Ipv6 => "ipv6",
+        }
+    }
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct IpCheckResult {
+    pub is_fastly_ip: bool,
+    /// The queried address in its canonical textual form.
+    pub ip: IpAddr,
+    pub family: AddressFamily,
+    /// The most specific Fastly prefix containing the address.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matched_prefix: Option<IpNet>,
+    /// The IPv4 address carried by an IPv6 transition format, checked
+    /// against the IPv4 list.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub embedded_ipv4: Option<EmbeddedIpv4>,
+}
+
+/// A rendered response, independent of the HTTP library that will send it.
+#[derive(Debug)]
+pub struct Reply {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: String,
+}
+
+impl Reply {
+    pub fn text(status: u16, body: &str) -> Self {
+        Reply {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            headers: Vec::new(),
+            body: body.to_owned(),
+        }
+    }
+
+    pub fn json<T: Serialize>(status: u16, value: &T) -> Result<Self> {
+        Ok(Reply {
+            status,
+            content_type: "application/json",
+            headers: Vec::new(),
+            body: serde_json::to_string(value)?,
+        })
+    }
+
+    /// Renders a lookup result in the negotiated format.
+    pub fn render<T: Render>(status: u16, value: &T, format: Format) -> Result<Self> {
+        Ok(Reply {
+            status,
+            content_type: format.content_type(),
+            headers: vec![("vary", "Accept".to_owned())],
+            body: value.render(format)?,
+        })
+    }
+
+    pub fn with_headers(mut self, headers: Vec<(&'static str, String)>) -> Self {
+        self.headers.extend(headers);
+        self
+    }
+}
@@ -0,0 +1,240 @@
+//! Maps the method and path of a request to what it asks for.
+//!
+//! Every endpoint lives under `/v1/`, and the older unversioned paths are
+//! kept as aliases. Any other path shaped like an address is read as the
+//! legacy `/{query}` lookup, so that links to `/151.101.230.73` keep working.
+//!
+//! `HEAD` is allowed wherever `GET` is.
+
+use crate::addr;
+use crate::response::Reply;
+
+const GET: &[&str] = &["GET", "HEAD"];
+const POST: &[&str] = &["POST"];
+const GET_POST: &[&str] = &["GET", "HEAD", "POST"];
+
+/// An endpoint, with the parts of the path its handler needs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Route<'a> {
+    /// `/v1/me`: the address the request itself came from.
+    Me,
+    Diagnostics,
+    Diff,
+    /// `/v1/providers/{ip}`
+    Providers(&'a str),
+    /// `/v1/export/{format}`
+    Export(&'a str),
+    Batch,
+    Forwarded,
+    /// `/v1/ip/{addr}`: a single address.
+    Ip(&'a str),
+    /// `/v1/cidr/{net}`: a network in CIDR notation.
+    Cidr(&'a str),
+    /// `/v1/ranges`: the list itself.
+    Ranges,
+    Health,
+    /// `/v1/refresh`: fetches the list from the origin and records it in the
+    /// history.
+    Refresh,
+    /// The legacy `/{query}`: an address, network or range, given with the
+    /// leading slash.
+    Lookup(&'a str),
+}
+
+/// Why no endpoint answers a request.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RouteError {
+    NotFound,
+    /// The path exists, but only for the listed methods.
+    MethodNotAllowed(&'static [&'static str]),
+}
+
+impl RouteError {
+    pub fn reply(self) -> Reply {
+        match self {
+            RouteError::NotFound => Reply::text(404, "Not found"),
+            RouteError::MethodNotAllowed(methods) => {
+                let listed = match methods.split_last() {
+                    Some((last, [])) => (*last).to_owned(),
+                    Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+                    None => String::new(),
+                };
+                Reply::text(405, &format!("Only {} allowed", listed))
+                    .with_headers(vec![("allow", methods.join(", "))])
+            }
+        }
+    }
+}
+
+/// Whether a legacy path looks like an address, network or range, so that
+/// paths such as `/favicon.ico` or `/cafe` are not taken for lookups. Every
+/// address has a `.` or `:`, or is bracketed. Zone IDs, after a `%`, are
+/// left to the parser.
+fn is_address_shaped(path: &str) -> bool {
+    let decoded = match addr::percent_decode(path) {
+        Ok(decoded) => decoded,
+        Err(_) => return false,
+    };
+    let value = decoded.trim_start_matches('/');
+    let unzoned = value.split('%').next().unwrap_or_default();
+    (unzoned.starts_with('[') || unzoned.contains(&['.', ':'][..]))
+        && unzoned
+            .chars()
+            .all(|c| c.is_ascii_hexdigit() || ".:/-[]".contains(c))
+}
+
+fn match_versioned(path: &str) -> Option<(Route<'_>, &'static [&'static str])> {
+    let found = match path {
+        "/me" => (Route::Me, GET),
+        "/diagnostics" => (Route::Diagnostics, GET),
+        "/diff" => (Route::Diff, GET),
+        "/batch" => (Route::Batch, POST),
+        "/forwarded" => (Route::Forwarded, GET_POST),
+        "/ranges" => (Route::Ranges, GET),
+        "/health" => (Route::Health, GET),
+        "/refresh" => (Route::Refresh, POST),
+        _ => {
+            if let Some(ip) = path.strip_prefix("/providers/") {
+                (Route::Providers(ip), GET)
+            } else if let Some(name) = path.strip_prefix("/export/") {
+                (Route::Export(name), GET)
+            } else if let Some(addr) = path.strip_prefix("/ip/") {
+                (Route::Ip(addr), GET)
+            } else if let Some(net) = path.strip_prefix("/cidr/") {
+                (Route::Cidr(net), GET)
+            } else {
+                return None;
+            }
+        }
+    };
+    Some(found)
+}
+
+fn match_path(path: &str) -> Option<(Route<'_>, &'static [&'static str])> {
+    // Only `/v1/...` is versioned: `/v1` alone or `/v1x` is nothing.
+    if let Some(rest) = path.strip_prefix("/v1") {
+        return if rest.starts_with('/') {
+            match_versioned(rest)
+        } else {
+            None
+        };
+    }
+    match path {
+        "/" => Some((Route::Me, GET)),
+        "/me" | "/diagnostics" | "/diff" | "/batch" | "/forwarded" => match_versioned(path),
+        _ if path.starts_with("/providers/") || path.starts_with("/export/") => {
+            match_versioned(path)
+        }
+        _ if is_address_shaped(path) => Some((Route::Lookup(path), GET)),
+        _ => None,
+    }
+}
+
+/// Finds the endpoint for a request.
+pub fn route<'a>(method: &str, path: &'a str) -> Result<Route<'a>, RouteError> {
+    let (route, methods) = match_path(path).ok_or(RouteError::NotFound)?;
+    if !methods.contains(&method) {
+        return Err(RouteError::MethodNotAllowed(methods));
+    }
+    Ok(route)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes() {
+        assert_eq!(route("GET", "/v1/me"), Ok(Route::Me));
+        assert_eq!(route("GET", "/v1/health"), Ok(Route::Health));
+        assert_eq!(route("GET", "/v1/ranges"), Ok(Route::Ranges));
+        assert_eq!(route("GET", "/v1/diagnostics"), Ok(Route::Diagnostics));
+        assert_eq!(route("GET", "/v1/diff"), Ok(Route::Diff));
+        assert_eq!(route("POST", "/v1/batch"), Ok(Route::Batch));
+        assert_eq!(route("POST", "/v1/forwarded"), Ok(Route::Forwarded));
+        assert_eq!(route("POST", "/v1/refresh"), Ok(Route::Refresh));
+        assert_eq!(
+            route("GET", "/v1/ip/151.101.230.73"),
+            Ok(Route::Ip("151.101.230.73"))
+        );
+        assert_eq!(
+            route("GET", "/v1/cidr/151.101.64.0/22"),
+            Ok(Route::Cidr("151.101.64.0/22"))
+        );
+        assert_eq!(route("GET", "/v1/export/nginx"), Ok(Route::Export("nginx")));
+        assert_eq!(
+            route("GET", "/v1/providers/104.16.1.1"),
+            Ok(Route::Providers("104.16.1.1"))
+        );
+        assert_eq!(route("HEAD", "/v1/ranges"), Ok(Route::Ranges));
+        assert_eq!(route("HEAD", "/v1/forwarded"), Ok(Route::Forwarded));
+    }
+
+    #[test]
+    fn legacy_aliases() {
+        assert_eq!(route("GET", "/"), Ok(Route::Me));
+        assert_eq!(route("GET", "/me"), Ok(Route::Me));
+        assert_eq!(route("GET", "/diagnostics"), Ok(Route::Diagnostics));
+        assert_eq!(route("GET", "/diff"), Ok(Route::Diff));
+        assert_eq!(route("POST", "/batch"), Ok(Route::Batch));
+        assert_eq!(route("POST", "/forwarded"), Ok(Route::Forwarded));
+        assert_eq!(route("GET", "/export/nginx"), Ok(Route::Export("nginx")));
+        assert_eq!(
+            route("GET", "/providers/104.16.1.1"),
+            Ok(Route::Providers("104.16.1.1"))
+        );
+    }
+
+    #[test]
+    fn legacy_lookups() {
+        for path in &[
+            "/151.101.230.73",
+            "/151.101.64.0/22",
+            "/2a04:4e42::/48",
+            "/151.101.0.0-151.101.3.255",
+            "/[2a04:4e42::1]",
+            "/fe80::1%25eth0",
+            "/%5B2a04:4e42::1%5D",
+            "/151.101.1.1/",
+        ] {
+            assert_eq!(route("GET", path), Ok(Route::Lookup(path)), "{}", path);
+        }
+        assert_eq!(
+            route("HEAD", "/151.101.230.73"),
+            Ok(Route::Lookup("/151.101.230.73"))
+        );
+        for path in &[
+            "/favicon.ico",
+            "/robots.txt",
+            "/health",
+            "/ip/151.101.1.1",
+            "/cafe",
+            "/dead",
+            "/bad",
+        ] {
+            assert_eq!(route("GET", path), Err(RouteError::NotFound), "{}", path);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(route("GET", "/v1/nope"), Err(RouteError::NotFound));
+        assert_eq!(route("GET", "/v1"), Err(RouteError::NotFound));
+        assert_eq!(route("GET", "/v1me"), Err(RouteError::NotFound));
+        assert_eq!(route("GET", "/v1/151.101.1.1"), Err(RouteError::NotFound));
+        assert_eq!(
+            route("GET", "/v1/batch"),
+            Err(RouteError::MethodNotAllowed(POST))
+        );
+        assert_eq!(
+            route("DELETE", "/151.101.230.73"),
+            Err(RouteError::MethodNotAllowed(GET))
+        );
+
+        let reply = route("PUT", "/v1/forwarded").unwrap_err().reply();
+        assert_eq!(reply.status, 405);
+        assert_eq!(reply.headers, vec![("allow", "GET, HEAD, POST".to_owned())]);
+        assert_eq!(reply.body, "Only GET, HEAD and POST allowed");
+        assert_eq!(RouteError::NotFound.reply().status, 404);
+    }
+}
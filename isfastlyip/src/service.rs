@@ -0,0 +1,52 @@
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+use crate::embedded::{self, EmbeddedIpv4};
+use crate::matcher::IpMatcher;
+use crate::response::{AddressFamily, IpCheckResult};
+
+/// The listed network containing the address, or else the IPv4 address it
+/// embeds.
+pub fn lookup_embedded(addr: IpAddr, matcher: &IpMatcher) -> Option<IpNet> {
+    matcher.lookup(addr).or_else(|| match addr {
+        IpAddr::V6(ipv6) => {
+            embedded::unwrap_ipv4(ipv6).and_then(|(_, ipv4)| matcher.lookup(IpAddr::V4(ipv4)))
+        }
+        IpAddr::V4(_) => None,
+    })
+}
+
+/// Checks a single address against the compiled list.
+///
+/// An IPv6 address embedding an IPv4 one is a Fastly address when either of
+/// them is listed.
+pub fn check_ip(addr: IpAddr, matcher: &IpMatcher) -> IpCheckResult {
+    let matched_prefix = matcher.lookup(addr);
+    let embedded_ipv4 = match addr {
+        IpAddr::V6(ipv6) => embedded::unwrap_ipv4(ipv6).map(|(embedding, ipv4)| {
+            let matched_prefix = matcher.lookup(IpAddr::V4(ipv4));
+            EmbeddedIpv4 {
+                embedding,
+                ip: ipv4,
+                is_fastly_ip: matched_prefix.is_some(),
+                matched_prefix,
+            }
+        }),
+        IpAddr::V4(_) => None,
+    };
+    let embedded_match = matches!(
+        embedded_ipv4,
+        Some(EmbeddedIpv4 {
+            is_fastly_ip: true,
+            ..
+        })
+    );
+
+    IpCheckResult {
+        is_fastly_ip: matched_prefix.is_some() || embedded_match,
+        ip: addr,
+        family: AddressFamily::of(addr),
+        matched_prefix,
+        embedded_ipv4,
+    }
+}
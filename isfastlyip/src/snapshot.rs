@@ -0,0 +1,26 @@
+//! The copy of `public-ip-list` compiled into the binary by `build.rs`.
+
+use crate::list::{FastlyIpList, IpRanges};
+
+include!(concat!(env!("OUT_DIR"), "/snapshot.rs"));
+
+pub fn ip_list() -> FastlyIpList {
+    FastlyIpList::from_json(SNAPSHOT_JSON.as_bytes()).expect("snapshot is checked by build.rs")
+}
+
+pub fn ranges() -> IpRanges {
+    ip_list().ranges().expect("snapshot holds invalid networks")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_parses() {
+        assert_eq!(SNAPSHOT_DATE.len(), "2021-03-19".len());
+        let ranges = ranges();
+        assert!(!ranges.ipv4.is_empty());
+        assert!(!ranges.ipv6.is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+use anyhow::{bail, Context, Result};
+
+/// Days between 1970-01-01 and the given date of the proleptic Gregorian
+/// calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn number(input: &str, field: &str, min: i64, max: i64) -> Result<i64> {
+    if input.is_empty() || !input.bytes().all(|byte| byte.is_ascii_digit()) {
+        bail!("Invalid {} {:?}", field, input);
+    }
+    let value: i64 = input.parse()?;
+    if value < min || value > max {
+        bail!("{} {} is out of range", field, value);
+    }
+    Ok(value)
+}
+
+/// Parses an RFC 3339 timestamp, such as `2021-03-19T12:00:00Z` or
+/// `2021-03-19T14:00:00.5+02:00`, into Unix seconds. Fractions of a second
+/// are dropped.
+pub fn parse_rfc3339(input: &str) -> Result<i64> {
+    let input = input.trim();
+    let invalid = || format!("{:?} is not an RFC 3339 timestamp", input);
+    if input.len() < 20 || !input.is_char_boundary(10) || !input.is_char_boundary(19) {
+        bail!(invalid());
+    }
+
+    let (date, rest) = input.split_at(10);
+    let date: Vec<&str> = date.split('-').collect();
+    if date.len() != 3 || date[0].len() != 4 || date[1].len() != 2 || date[2].len() != 2 {
+        bail!(invalid());
+    }
+    let year = number(date[0], "year", 0, 9999)?;
+    let month = number(date[1], "month", 1, 12)?;
+    let day = number(date[2], "day", 1, days_in_month(year, month))?;
+
+    if !matches!(rest.as_bytes()[0], b'T' | b't' | b' ') {
+        bail!(invalid());
+    }
+    let (time, mut zone) = rest[1..].split_at(8);
+    let time: Vec<&str> = time.split(':').collect();
+    if time.len() != 3 || time.iter().any(|part| part.len() != 2) {
+        bail!(invalid());
+    }
+    let hour = number(time[0], "hour", 0, 23)?;
+    let minute = number(time[1], "minute", 0, 59)?;
+    // A leap second is read as the last second of its minute.
+    let second = number(time[2], "second", 0, 60)?.min(59);
+
+    if let Some(fraction) = zone.strip_prefix('.') {
+        let digits = fraction
+            .find(|c: char| !c.is_ascii_digit())
+            .with_context(invalid)?;
+        if digits == 0 {
+            bail!(invalid());
+        }
+        zone = &fraction[digits..];
+    }
+    let offset = match zone {
+        "Z" | "z" => 0,
+        _ if zone.len() == 6 && zone.is_ascii() && &zone[3..4] == ":" => {
+            let sign = match &zone[..1] {
+                "+" => 1,
+                "-" => -1,
+                _ => bail!(invalid()),
+            };
+            let hours = number(&zone[1..3], "offset hour", 0, 23)?;
+            let minutes = number(&zone[4..6], "offset minute", 0, 59)?;
+            sign * (hours * 3600 + minutes * 60)
+        }
+        _ => bail!(invalid()),
+    };
+
+    let days = days_from_civil(year, month, day);
+    Ok(days * 86400 + hour * 3600 + minute * 60 + second - offset)
+}
+
+/// Formats Unix seconds as an RFC 3339 timestamp in UTC.
+pub fn format_rfc3339(seconds: i64) -> String {
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let time = seconds.rem_euclid(86400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_timestamps() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z").unwrap(), 0);
+        assert_eq!(
+            parse_rfc3339("2021-03-19T12:00:00Z").unwrap(),
+            1_616_155_200
+        );
+        assert_eq!(
+            parse_rfc3339("2021-03-19T14:00:00.123+02:00").unwrap(),
+            1_616_155_200
+        );
+        assert_eq!(
+            parse_rfc3339("2020-02-29t00:00:00-00:30").unwrap(),
+            1_582_936_200
+        );
+
+        for invalid in &[
+            "2021-03-19",
+            "2021-02-29T00:00:00Z",
+            "2021-03-19T24:00:00Z",
+            "2021-03-19T12:00:00",
+            "2021-03-19T12:00:00.Z",
+            "2021-03-19T12:00:00+0200",
+            "2021-3-19T12:00:00Z",
+        ] {
+            assert!(parse_rfc3339(invalid).is_err(), "{} was accepted", invalid);
+        }
+    }
+
+    #[test]
+    fn format_timestamps() {
+        assert_eq!(format_rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_rfc3339(1_582_936_200), "2020-02-29T00:30:00Z");
+        assert_eq!(format_rfc3339(-1), "1969-12-31T23:59:59Z");
+    }
+}
@@ -0,0 +1,166 @@
+/// A binary trie keyed by the leading bits of an address.
+///
+/// Keys are left-aligned in a `u128`, so the same structure serves both
+/// IPv4 prefixes (32 significant bits) and IPv6 prefixes (128 significant
+/// bits). A lookup walks at most one node per bit of the key, independent
+/// of how many prefixes have been inserted.
+pub struct PrefixTrie<V> {
+    nodes: Vec<Node<V>>,
+    len: usize,
+}
+
+struct Node<V> {
+    children: [Option<usize>; 2],
+    value: Option<V>,
+}
+
+impl<V> Node<V> {
+    fn new() -> Self {
+        Node {
+            children: [None, None],
+            value: None,
+        }
+    }
+}
+
+fn bit_at(key: u128, index: u8) -> usize {
+    ((key >> (127 - index)) & 1) as usize
+}
+
+impl<V> PrefixTrie<V> {
+    pub fn new() -> Self {
+        PrefixTrie {
+            nodes: vec![Node::new()],
+            len: 0,
+        }
+    }
+
+    /// Number of prefixes stored in the trie.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Stores `value` under the first `prefix_len` bits of `key`, returning
+    /// the value previously stored under the same prefix, if any.
+    pub fn insert(&mut self, key: u128, prefix_len: u8, value: V) -> Option<V> {
+        assert!(prefix_len <= 128, "prefix length out of range");
+
+        let mut node = 0;
+        for index in 0..prefix_len {
+            let bit = bit_at(key, index);
+            node = match self.nodes[node].children[bit] {
+                Some(child) => child,
+                None => {
+                    self.nodes.push(Node::new());
+                    let child = self.nodes.len() - 1;
+                    self.nodes[node].children[bit] = Some(child);
+                    child
+                }
+            };
+        }
+
+        let previous = self.nodes[node].value.replace(value);
+        if previous.is_none() {
+            self.len += 1;
+        }
+        previous
+    }
+
+    /// Returns the value stored under the longest prefix of `key`.
+    pub fn longest_match(&self, key: u128) -> Option<&V> {
+        let mut node = 0;
+        let mut found = self.nodes[node].value.as_ref();
+        for index in 0..128 {
+            node = match self.nodes[node].children[bit_at(key, index)] {
+                Some(child) => child,
+                None => break,
+            };
+            if let Some(value) = self.nodes[node].value.as_ref() {
+                found = Some(value);
+            }
+        }
+        found
+    }
+
+    /// Returns every value whose prefix overlaps the first `prefix_len` bits
+    /// of `key`: the prefixes containing it, then the prefixes it contains.
+    pub fn intersecting(&self, key: u128, prefix_len: u8) -> Vec<&V> {
+        assert!(prefix_len <= 128, "prefix length out of range");
+
+        let mut found = Vec::new();
+        let mut node = 0;
+        for index in 0..prefix_len {
+            if let Some(value) = self.nodes[node].value.as_ref() {
+                found.push(value);
+            }
+            node = match self.nodes[node].children[bit_at(key, index)] {
+                Some(child) => child,
+                None => return found,
+            };
+        }
+
+        let mut pending = vec![node];
+        while let Some(node) = pending.pop() {
+            if let Some(value) = self.nodes[node].value.as_ref() {
+                found.push(value);
+            }
+            pending.extend(self.nodes[node].children.iter().rev().flatten());
+        }
+        found
+    }
+}
+
+impl<V> Default for PrefixTrie<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trie_longest_match() {
+        let mut trie = PrefixTrie::new();
+        trie.insert(0xc0u128 << 120, 8, "/8");
+        trie.insert(0xc0a8u128 << 112, 16, "/16");
+
+        assert_eq!(trie.len(), 2);
+        assert_eq!(trie.longest_match(0xc0a8_0101u128 << 96), Some(&"/16"));
+        assert_eq!(trie.longest_match(0xc001_0101u128 << 96), Some(&"/8"));
+        assert_eq!(trie.longest_match(0x0a00_0001u128 << 96), None);
+    }
+
+    #[test]
+    fn trie_insert_replaces() {
+        let mut trie = PrefixTrie::new();
+        assert_eq!(trie.insert(0, 0, 1), None);
+        assert_eq!(trie.insert(0, 0, 2), Some(1));
+        assert_eq!(trie.len(), 1);
+        assert_eq!(trie.longest_match(u128::MAX), Some(&2));
+    }
+
+    #[test]
+    fn trie_intersecting() {
+        let mut trie = PrefixTrie::new();
+        trie.insert(0xc0u128 << 120, 8, "192/8");
+        trie.insert(0xc0a8_0000u128 << 96, 24, "192.168.0/24");
+        trie.insert(0xc0a8_0100u128 << 96, 24, "192.168.1/24");
+        trie.insert(0x0au128 << 120, 8, "10/8");
+
+        assert_eq!(
+            trie.intersecting(0xc0a8u128 << 112, 16),
+            vec![&"192/8", &"192.168.0/24", &"192.168.1/24"]
+        );
+        assert_eq!(
+            trie.intersecting(0xc0a8_0180u128 << 96, 25),
+            vec![&"192/8", &"192.168.1/24"]
+        );
+        assert!(trie.intersecting(0xacu128 << 120, 8).is_empty());
+    }
+}
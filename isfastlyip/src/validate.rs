@@ -0,0 +1,443 @@
+use ipnet::{Ipv4Net, Ipv6Net};
+use std::fmt;
+
+use crate::cache::CacheSettings;
+use crate::history::Version;
+use crate::list::{FastlyIpList, IpRanges, ListSource};
+use crate::matcher::IpMatcher;
+use crate::normalize::{self, NormalizationReport};
+use crate::snapshot;
+
+/// More networks than any plausible published list holds; a larger payload
+/// is taken as a sign of a broken or hostile upstream.
+pub const MAX_ENTRIES: usize = 100_000;
+
+/// Why an upstream response was not trusted at all.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValidationError {
+    Status(u16),
+    ContentType(String),
+    Json(String),
+    Empty,
+    TooLarge(usize),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationError::Status(status) => write!(f, "Backend returned status {}", status),
+            ValidationError::ContentType(content_type) => {
+                write!(f, "Backend returned content type {:?}", content_type)
+            }
+            ValidationError::Json(err) => write!(f, "Backend returned malformed JSON: {}", err),
+            ValidationError::Empty => write!(f, "Backend returned no usable networks"),
+            ValidationError::TooLarge(entries) => write!(
+                f,
+                "Backend returned {} entries, more than the limit of {}",
+                entries, MAX_ENTRIES
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// An entry of the list that was skipped.
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub field: &'static str,
+    pub index: usize,
+    pub entry: String,
+    pub error: String,
+}
+
+/// The usable part of a list, and what was skipped to get it.
+#[derive(Debug)]
+pub struct ValidatedList {
+    pub ranges: IpRanges,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+fn parse_entries<T, E>(
+    field: &'static str,
+    entries: &[String],
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<T>
+where
+    T: std::str::FromStr<Err = E>,
+    E: fmt::Display,
+{
+    let mut nets = Vec::with_capacity(entries.len());
+    for (index, entry) in entries.iter().enumerate() {
+        match entry.trim().parse::<T>() {
+            Ok(net) => nets.push(net),
+            Err(err) => diagnostics.push(Diagnostic {
+                field,
+                index,
+                entry: entry.clone(),
+                error: err.to_string(),
+            }),
+        }
+    }
+    nets
+}
+
+/// Parses every entry of the list, recording the ones that are not
+/// networks of the expected family instead of failing on them.
+pub fn validate_list(list: &FastlyIpList) -> Result<ValidatedList, ValidationError> {
+    let entries = list.addresses.len() + list.ipv6_addresses.len();
+    if entries > MAX_ENTRIES {
+        return Err(ValidationError::TooLarge(entries));
+    }
+
+    let mut diagnostics = Vec::new();
+    let ipv4 = parse_entries::<Ipv4Net, _>("addresses", &list.addresses, &mut diagnostics);
+    let ipv6 =
+        parse_entries::<Ipv6Net, _>("ipv6_addresses", &list.ipv6_addresses, &mut diagnostics);
+
+    let ranges = IpRanges { ipv4, ipv6 };
+    if ranges.is_empty() {
+        return Err(ValidationError::Empty);
+    }
+    Ok(ValidatedList {
+        ranges,
+        diagnostics,
+    })
+}
+
+/// Checks an upstream response before trusting its body.
+pub fn validate_response(
+    status: u16,
+    content_type: Option<&str>,
+    body: &[u8],
+) -> Result<ValidatedList, ValidationError> {
+    if !(200..300).contains(&status) {
+        return Err(ValidationError::Status(status));
+    }
+    if let Some(content_type) = content_type {
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+        if essence != "application/json" && !essence.ends_with("+json") {
+            return Err(ValidationError::ContentType(content_type.to_owned()));
+        }
+    }
+
+    let list =
+        FastlyIpList::from_json(body).map_err(|err| ValidationError::Json(err.to_string()))?;
+    validate_list(&list)
+}
+
+/// The list a request is answered from, with what was noticed loading it.
+#[derive(Debug)]
+pub struct LoadedList {
+    /// The normalized networks of the list, which exports and counts use.
+    pub ranges: IpRanges,
+    /// The networks as published, so that lookups report a prefix Fastly
+    /// published rather than one aggregated from it. Versions from the
+    /// history only keep their normalized networks.
+    pub published: IpRanges,
+    pub source: ListSource,
+    pub diagnostics: Vec<Diagnostic>,
+    pub normalization: NormalizationReport,
+    /// Why the live list was not used, when it was not.
+    pub fallback_reason: Option<String>,
+    /// Seconds since the live list was fetched from the API.
+    pub age: Option<u32>,
+    /// Whether the live list is past its TTL.
+    pub stale: bool,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct ListReport<'a> {
+    pub source: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshot_date: Option<&'static str>,
+    pub ipv4_networks: usize,
+    pub ipv6_networks: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub age: Option<u32>,
+    pub stale: bool,
+    pub skipped: &'a [Diagnostic],
+    pub normalization: &'a NormalizationReport,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback_reason: Option<&'a str>,
+}
+
+/// The answer to a health check. The service keeps answering from the
+/// snapshot when the live list is unavailable, so it is degraded rather than
+/// down.
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct Health {
+    /// `ok` when answering from a fresh live list, `degraded` otherwise.
+    pub status: &'static str,
+    pub source: &'static str,
+    pub stale: bool,
+}
+
+impl LoadedList {
+    pub fn live(validated: ValidatedList) -> Self {
+        let normalized = normalize::normalize(validated.ranges.clone());
+        LoadedList {
+            ranges: normalized.ranges,
+            published: validated.ranges,
+            source: ListSource::Live,
+            diagnostics: validated.diagnostics,
+            normalization: normalized.report,
+            fallback_reason: None,
+            age: None,
+            stale: false,
+        }
+    }
+
+    /// Matches addresses against the published networks. They cover the same
+    /// addresses as the normalized ones, and the narrowest containing an
+    /// address is reported.
+    pub fn matcher(&self) -> IpMatcher {
+        self.published.matcher()
+    }
+
+    /// Records how long the list has been cached.
+    pub fn with_age(mut self, age: Option<u32>, settings: &CacheSettings) -> Self {
+        self.age = age;
+        self.stale = matches!(age, Some(age) if settings.is_stale(age));
+        self
+    }
+
+    pub fn snapshot(fallback_reason: String) -> Self {
+        let published = snapshot::ranges();
+        let normalized = normalize::normalize(published.clone());
+        LoadedList {
+            ranges: normalized.ranges,
+            published,
+            source: ListSource::Snapshot(snapshot::SNAPSHOT_DATE),
+            diagnostics: Vec::new(),
+            normalization: normalized.report,
+            fallback_reason: Some(fallback_reason),
+            age: None,
+            stale: false,
+        }
+    }
+
+    /// A version of the list from its history.
+    pub fn historical(version: &Version, ranges: IpRanges) -> Self {
+        LoadedList {
+            published: ranges.clone(),
+            ranges,
+            source: ListSource::History {
+                first_seen: version.first_seen,
+                last_seen: version.last_seen,
+            },
+            diagnostics: Vec::new(),
+            normalization: NormalizationReport::default(),
+            fallback_reason: None,
+            age: None,
+            stale: false,
+        }
+    }
+
+    /// The last version from the history, served stale because the live list
+    /// could not be loaded. `age` is the time since a refresh last saw it.
+    pub fn stale_if_error(
+        version: &Version,
+        ranges: IpRanges,
+        age: u32,
+        fallback_reason: String,
+    ) -> Self {
+        LoadedList {
+            fallback_reason: Some(fallback_reason),
+            age: Some(age),
+            stale: true,
+            ..LoadedList::historical(version, ranges)
+        }
+    }
+
+    /// Response headers describing where the list came from, how old it is
+    /// and how many of its entries were skipped.
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = self.source.headers();
+        if let Some(age) = self.age {
+            headers.push(("x-ip-list-age", age.to_string()));
+        }
+        if self.stale {
+            headers.push(("x-ip-list-stale", "true".to_owned()));
+        }
+        if !self.diagnostics.is_empty() {
+            headers.push((
+                "x-ip-list-skipped-entries",
+                self.diagnostics.len().to_string(),
+            ));
+        }
+        headers
+    }
+
+    pub fn report(&self) -> ListReport<'_> {
+        let (source, snapshot_date) = match self.source {
+            ListSource::Live => ("live", None),
+            ListSource::Snapshot(date) => ("snapshot", Some(date)),
+            ListSource::History { .. } => ("history", None),
+        };
+        ListReport {
+            source,
+            snapshot_date,
+            ipv4_networks: self.ranges.ipv4.len(),
+            ipv6_networks: self.ranges.ipv6.len(),
+            age: self.age,
+            stale: self.stale,
+            skipped: &self.diagnostics,
+            normalization: &self.normalization,
+            fallback_reason: self.fallback_reason.as_deref(),
+        }
+    }
+
+    pub fn health(&self) -> Health {
+        let status = if self.source == ListSource::Live && !self.stale {
+            "ok"
+        } else {
+            "degraded"
+        };
+        Health {
+            status,
+            source: self.report().source,
+            stale: self.stale,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] =
+        br#"{"addresses":["151.101.0.0/16","bogus","2a04:4e40::/32"],"ipv6_addresses":["2a04:4e42::/32"]}"#;
+
+    #[test]
+    fn skips_bad_entries() {
+        let validated = validate_response(200, Some("application/json"), BODY).unwrap();
+
+        assert_eq!(validated.ranges.ipv4.len(), 1);
+        assert_eq!(validated.ranges.ipv6.len(), 1);
+        assert_eq!(
+            validated
+                .diagnostics
+                .iter()
+                .map(|diagnostic| (diagnostic.field, diagnostic.index))
+                .collect::<Vec<_>>(),
+            vec![("addresses", 1), ("addresses", 2)]
+        );
+    }
+
+    #[test]
+    fn lookups_report_published_prefixes() {
+        let body = br#"{"addresses":["151.101.0.0/17","151.101.128.0/17"],"ipv6_addresses":[]}"#;
+        let validated = validate_response(200, Some("application/json"), body).unwrap();
+        let list = LoadedList::live(validated);
+
+        assert_eq!(list.ranges.ipv4, vec!["151.101.0.0/16".parse().unwrap()]);
+        assert_eq!(
+            list.matcher().lookup("151.101.200.1".parse().unwrap()),
+            Some("151.101.128.0/17".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn rejects_untrusted_responses() {
+        assert_eq!(
+            validate_response(503, Some("application/json"), BODY).unwrap_err(),
+            ValidationError::Status(503)
+        );
+        assert_eq!(
+            validate_response(200, Some("text/html; charset=utf-8"), BODY).unwrap_err(),
+            ValidationError::ContentType("text/html; charset=utf-8".to_owned())
+        );
+        assert!(matches!(
+            validate_response(200, None, b"<html>"),
+            Err(ValidationError::Json(_))
+        ));
+        assert_eq!(
+            validate_response(200, None, br#"{"addresses":[],"ipv6_addresses":["x"]}"#)
+                .unwrap_err(),
+            ValidationError::Empty
+        );
+
+        let list = FastlyIpList {
+            addresses: vec!["151.101.0.0/16".to_owned(); MAX_ENTRIES + 1],
+            ipv6_addresses: vec![],
+        };
+        assert_eq!(
+            validate_list(&list).unwrap_err(),
+            ValidationError::TooLarge(MAX_ENTRIES + 1)
+        );
+    }
+
+    #[test]
+    fn loaded_list_headers() {
+        let validated = validate_response(200, None, BODY).unwrap();
+        let settings = CacheSettings {
+            ttl: 60,
+            ..CacheSettings::default()
+        };
+        let headers = LoadedList::live(validated)
+            .with_age(Some(90), &settings)
+            .headers();
+        assert_eq!(
+            headers,
+            vec![
+                ("x-ip-list-source", "live".to_owned()),
+                ("x-ip-list-age", "90".to_owned()),
+                ("x-ip-list-stale", "true".to_owned()),
+                ("x-ip-list-skipped-entries", "2".to_owned()),
+            ]
+        );
+
+        let loaded = LoadedList::snapshot("Backend returned status 503".to_owned());
+        assert_eq!(loaded.report().source, "snapshot");
+        assert_eq!(loaded.headers().len(), 2);
+    }
+
+    #[test]
+    fn health() {
+        let validated = validate_response(200, None, BODY).unwrap();
+        let settings = CacheSettings {
+            ttl: 60,
+            ..CacheSettings::default()
+        };
+        let loaded = LoadedList::live(validated);
+        assert_eq!(loaded.health().status, "ok");
+        let health = loaded.with_age(Some(90), &settings).health();
+        assert_eq!(health.status, "degraded");
+        assert!(health.stale);
+
+        let health = LoadedList::snapshot("Backend returned status 503".to_owned()).health();
+        assert_eq!(
+            health,
+            Health {
+                status: "degraded",
+                source: "snapshot",
+                stale: false,
+            }
+        );
+
+        let version = Version {
+            hash: String::new(),
+            first_seen: 0,
+            last_seen: 0,
+        };
+        let loaded = LoadedList::stale_if_error(
+            &version,
+            IpRanges::default(),
+            7200,
+            "Backend returned status 503".to_owned(),
+        );
+        assert_eq!(
+            loaded.report().fallback_reason,
+            Some("Backend returned status 503")
+        );
+        assert_eq!(
+            loaded.health(),
+            Health {
+                status: "degraded",
+                source: "history",
+                stale: true,
+            }
+        );
+    }
+}
@@ -0,0 +1,512 @@
+//! Notifications, POSTed to webhook backends, that the published list
+//! changed.
+//!
+//! A refresh recording a new version queues its notifications in the
+//! history store and attempts them once it has answered. Failed deliveries
+//! are retried by later refreshes, and those running at the same time may
+//! both deliver, so receivers should deduplicate deliveries by the `hash` of
+//! the current version.
+
+use anyhow::{bail, Result};
+use hmac_sha256::HMAC;
+use ipnet::{Ipv4Net, Ipv6Net};
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::diff::{FamilyDiff, ListDiff};
+use crate::history::{SnapshotStore, Version};
+use crate::list::IpRanges;
+use crate::timestamp::format_rfc3339;
+
+/// The header carrying the signature of a delivery.
+pub const SIGNATURE_HEADER: &str = "x-isfastlyip-signature";
+
+/// The event of a change notification.
+pub const CHANGED_EVENT: &str = "ip_list.changed";
+
+/// Lowercase hex of some bytes, as signatures and version hashes are given.
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(out, "{:02x}", byte).expect("writing to a String never fails");
+    }
+    out
+}
+
+/// The most attempts a delivery gets, whatever is configured.
+pub const MAX_ATTEMPTS: u32 = 10;
+
+/// How long after its version was first seen a delivery is still attempted,
+/// in seconds.
+pub const DELIVERY_WINDOW: i64 = 24 * 60 * 60;
+
+/// How deliveries that fail are retried.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts made in total, including the first, up to [`MAX_ATTEMPTS`].
+    pub max_attempts: u32,
+    /// The wait before the first retry, doubled for every later one.
+    pub initial_backoff: Duration,
+    /// The longest wait between two attempts.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(60),
+            max_backoff: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The wait before retrying after the given failed attempt, counted
+    /// from 1.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt.saturating_sub(1)).unwrap_or(0);
+        match self.initial_backoff.checked_mul(factor) {
+            Some(backoff) if factor > 0 => backoff.min(self.max_backoff),
+            _ => self.max_backoff,
+        }
+    }
+}
+
+/// Whether a delivery answered with `status` is worth retrying.
+pub fn is_retryable(status: u16) -> bool {
+    matches!(status, 408 | 429 | 500..=599)
+}
+
+/// Where and how change notifications are delivered.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WebhookSettings {
+    /// The backends the notifications are POSTed to.
+    pub backends: Vec<String>,
+    /// The path the notifications are POSTed to on every backend.
+    pub path: String,
+    /// The key deliveries are signed with. They are sent unsigned without
+    /// one.
+    pub secret: Option<String>,
+    pub retry: RetryPolicy,
+}
+
+impl WebhookSettings {
+    /// Reads the settings from `webhook_backends`, a comma-separated list of
+    /// backend names, `webhook_path`, `webhook_secret` and
+    /// `webhook_max_attempts`.
+    pub fn from_lookup<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let backends = lookup("webhook_backends")
+            .map(|names| {
+                names
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_owned)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let path = lookup("webhook_path")
+            .map(|path| path.trim().to_owned())
+            .filter(|path| path.starts_with('/'))
+            .unwrap_or_else(|| "/".to_owned());
+        let secret = lookup("webhook_secret").filter(|secret| !secret.is_empty());
+        let mut retry = RetryPolicy::default();
+        if let Some(attempts) = lookup("webhook_max_attempts")
+            .and_then(|value| value.trim().parse::<u32>().ok())
+            .filter(|attempts| *attempts > 0)
+        {
+            retry.max_attempts = attempts.min(MAX_ATTEMPTS);
+        }
+        WebhookSettings {
+            backends,
+            path,
+            secret,
+            retry,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.backends.is_empty()
+    }
+}
+
+/// A version of the list as described in a notification.
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct VersionInfo {
+    pub hash: String,
+    pub first_seen: String,
+}
+
+impl VersionInfo {
+    fn new(version: &Version) -> Self {
+        VersionInfo {
+            hash: version.hash.clone(),
+            first_seen: format_rfc3339(version.first_seen),
+        }
+    }
+}
+
+/// The prefixes of one address family that were added and removed.
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct FamilyChanges<N> {
+    pub added: Vec<N>,
+    pub removed: Vec<N>,
+}
+
+impl<N> From<FamilyDiff<N>> for FamilyChanges<N> {
+    fn from(diff: FamilyDiff<N>) -> Self {
+        FamilyChanges {
+            added: diff.added,
+            removed: diff.removed,
+        }
+    }
+}
+
+/// The payload of a change notification.
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct ChangeEvent {
+    pub event: &'static str,
+    pub previous: VersionInfo,
+    pub current: VersionInfo,
+    pub ipv4: FamilyChanges<Ipv4Net>,
+    pub ipv6: FamilyChanges<Ipv6Net>,
+}
+
+impl ChangeEvent {
+    /// Describes the change between two normalized versions of the list.
+    pub fn new(
+        previous: &Version,
+        previous_ranges: &IpRanges,
+        current: &Version,
+        current_ranges: &IpRanges,
+    ) -> Self {
+        let diff = ListDiff::new(
+            String::new(),
+            previous_ranges,
+            String::new(),
+            current_ranges,
+        );
+        ChangeEvent {
+            event: CHANGED_EVENT,
+            previous: VersionInfo::new(previous),
+            current: VersionInfo::new(current),
+            ipv4: diff.ipv4.into(),
+            ipv6: diff.ipv6.into(),
+        }
+    }
+
+    /// The body of a delivery, which is what gets signed.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+}
+
+/// Signs a delivery made at `timestamp`, in Unix seconds, as
+/// `t=<timestamp>,v1=<hex HMAC-SHA256 of "<timestamp>.<body>">`. The
+/// timestamp is covered so that a captured delivery cannot be replayed
+/// later.
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    format!("t={},v1={}", timestamp, hex(&mac(secret, timestamp, body)))
+}
+
+fn mac(secret: &str, timestamp: i64, body: &[u8]) -> [u8; 32] {
+    let mut mac = HMAC::new(secret);
+    mac.update(timestamp.to_string());
+    mac.update(b".");
+    mac.update(body);
+    mac.finalize()
+}
+
+/// Checks the signature header of a delivery received at `now`, rejecting
+/// ones signed more than `tolerance` seconds away from it.
+pub fn verify(secret: &str, header: &str, body: &[u8], now: i64, tolerance: i64) -> Result<()> {
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for part in header.split(',') {
+        let mut pair = part.trim().splitn(2, '=');
+        match (pair.next(), pair.next()) {
+            (Some("t"), Some(value)) => timestamp = value.parse::<i64>().ok(),
+            (Some("v1"), Some(value)) => signatures.push(value),
+            _ => {}
+        }
+    }
+
+    let timestamp = match timestamp {
+        Some(timestamp) => timestamp,
+        None => bail!("The signature has no timestamp"),
+    };
+    if (now - timestamp).abs() > tolerance {
+        bail!("The signature timestamp is outside the tolerance");
+    }
+    let expected = hex(&mac(secret, timestamp, body));
+    if !signatures
+        .iter()
+        .any(|signature| constant_time_eq(signature.as_bytes(), expected.as_bytes()))
+    {
+        bail!("No signature matches the body");
+    }
+    Ok(())
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// How a delivery attempt ended.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Delivered,
+    /// The attempt failed and is retried once the backoff has passed.
+    Retry,
+    /// The attempt failed permanently or was the last one allowed.
+    GaveUp,
+}
+
+/// A notification waiting to be delivered to one backend.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+pub struct PendingDelivery {
+    pub backend: String,
+    /// The body of the notification, which is what gets signed.
+    pub body: String,
+    /// The attempts made so far.
+    pub attempts: u32,
+    /// When the next attempt is due, in Unix seconds.
+    pub next_attempt: i64,
+    /// Why the last attempt failed, if one was made.
+    pub last_error: Option<String>,
+}
+
+impl PendingDelivery {
+    fn record_attempt(&mut self, result: Result<u16>, policy: &RetryPolicy, now: i64) -> Outcome {
+        self.attempts += 1;
+        let (error, retryable) = match result {
+            Ok(status) if (200..300).contains(&status) => {
+                self.last_error = None;
+                return Outcome::Delivered;
+            }
+            Ok(status) => (format!("Webhook answered {}", status), is_retryable(status)),
+            Err(err) => (err.to_string(), true),
+        };
+        self.last_error = Some(error);
+        if !retryable || self.attempts >= policy.max_attempts.min(MAX_ATTEMPTS) {
+            return Outcome::GaveUp;
+        }
+        self.next_attempt = now + policy.backoff(self.attempts).as_secs() as i64;
+        Outcome::Retry
+    }
+}
+
+fn deliveries_key(hash: &str) -> String {
+    format!("deliveries/{}", hash)
+}
+
+/// Queues the notification of `event` for every backend, in the history
+/// store next to the version it announces.
+pub fn enqueue<S: SnapshotStore>(
+    store: &mut S,
+    event: &ChangeEvent,
+    backends: &[String],
+    now: i64,
+) -> Result<()> {
+    let body = event.to_json()?;
+    let deliveries: Vec<_> = backends
+        .iter()
+        .map(|backend| PendingDelivery {
+            backend: backend.clone(),
+            body: body.clone(),
+            attempts: 0,
+            next_attempt: now,
+            last_error: None,
+        })
+        .collect();
+    store.put(
+        &deliveries_key(&event.current.hash),
+        serde_json::to_string(&deliveries)?,
+    )
+}
+
+/// Makes one attempt at every due delivery of the version with the given
+/// hash, with `send`, which returns the response status, and keeps the ones
+/// to retry. Nothing waits between attempts: a later call retries a failed
+/// delivery once its backoff has passed.
+pub fn process<S, F>(
+    store: &mut S,
+    hash: &str,
+    policy: &RetryPolicy,
+    now: i64,
+    mut send: F,
+) -> Result<Vec<(PendingDelivery, Outcome)>>
+where
+    S: SnapshotStore,
+    F: FnMut(&PendingDelivery) -> Result<u16>,
+{
+    let key = deliveries_key(hash);
+    let pending: Vec<PendingDelivery> = match store.get(&key)? {
+        Some(json) => serde_json::from_str(&json)?,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut kept = Vec::new();
+    let mut attempted = Vec::new();
+    for mut delivery in pending {
+        if delivery.next_attempt > now {
+            kept.push(delivery);
+            continue;
+        }
+        let outcome = delivery.record_attempt(send(&delivery), policy, now);
+        if outcome == Outcome::Retry {
+            kept.push(delivery.clone());
+        }
+        attempted.push((delivery, outcome));
+    }
+    if !attempted.is_empty() {
+        store.put(&key, serde_json::to_string(&kept)?)?;
+    }
+    Ok(attempted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::{content_hash, MemoryStore};
+    use anyhow::anyhow;
+
+    fn ranges(nets: &[&str]) -> IpRanges {
+        let mut ranges = IpRanges::default();
+        for net in nets {
+            ranges.push(net.parse().unwrap());
+        }
+        ranges
+    }
+
+    #[test]
+    fn signatures() {
+        let body = br#"{"event":"ip_list.changed"}"#;
+        let signature = sign("secret", 1_616_155_200, body);
+        assert!(signature.starts_with("t=1616155200,v1="));
+
+        assert!(verify("secret", &signature, body, 1_616_155_260, 300).is_ok());
+        assert!(verify("other", &signature, body, 1_616_155_260, 300).is_err());
+        assert!(verify("secret", &signature, b"{}", 1_616_155_260, 300).is_err());
+        assert!(verify("secret", &signature, body, 1_616_156_000, 300).is_err());
+        assert!(verify("secret", "v1=00", body, 1_616_155_200, 300).is_err());
+    }
+
+    fn event() -> ChangeEvent {
+        let old = ranges(&["151.101.0.0/16"]);
+        let new = ranges(&["151.101.0.0/16", "146.75.0.0/17"]);
+        let version = |first_seen, ranges| Version {
+            hash: content_hash(ranges),
+            first_seen,
+            last_seen: first_seen,
+        };
+        ChangeEvent::new(&version(0, &old), &old, &version(1000, &new), &new)
+    }
+
+    #[test]
+    fn retries_with_backoff() {
+        let policy = RetryPolicy::default();
+        let event = event();
+        let hash = event.current.hash.clone();
+        let mut store = MemoryStore::default();
+        enqueue(&mut store, &event, &["hooks".to_owned()], 1000).unwrap();
+
+        let attempted = process(&mut store, &hash, &policy, 1000, |delivery| {
+            assert_eq!(delivery.body, event.to_json().unwrap());
+            Err(anyhow!("connection refused"))
+        })
+        .unwrap();
+        assert_eq!(attempted.len(), 1);
+        assert_eq!(attempted[0].1, Outcome::Retry);
+        assert_eq!(attempted[0].0.next_attempt, 1060);
+
+        let attempted = process(&mut store, &hash, &policy, 1059, |_| panic!("not due")).unwrap();
+        assert!(attempted.is_empty());
+        let attempted = process(&mut store, &hash, &policy, 1060, |_| Ok(503)).unwrap();
+        assert_eq!(attempted[0].1, Outcome::Retry);
+        assert_eq!(attempted[0].0.next_attempt, 1180);
+        let attempted = process(&mut store, &hash, &policy, 1180, |_| Ok(204)).unwrap();
+        assert_eq!(attempted[0].0.attempts, 3);
+        assert_eq!(attempted[0].1, Outcome::Delivered);
+
+        let attempted = process(&mut store, &hash, &policy, 9999, |_| panic!("delivered")).unwrap();
+        assert!(attempted.is_empty());
+        assert_eq!(policy.backoff(10), policy.max_backoff);
+        assert_eq!(policy.backoff(40), policy.max_backoff);
+    }
+
+    #[test]
+    fn gives_up() {
+        let policy = RetryPolicy::default();
+        let event = event();
+        let hash = event.current.hash.clone();
+        let mut store = MemoryStore::default();
+        let backends = ["hooks".to_owned(), "audit".to_owned()];
+        enqueue(&mut store, &event, &backends, 0).unwrap();
+
+        let attempted = process(&mut store, &hash, &policy, 0, |delivery| {
+            Ok(if delivery.backend == "hooks" {
+                400
+            } else {
+                500
+            })
+        })
+        .unwrap();
+        assert_eq!(attempted[0].1, Outcome::GaveUp);
+        assert_eq!(
+            attempted[0].0.last_error.as_deref(),
+            Some("Webhook answered 400")
+        );
+        assert_eq!(attempted[1].1, Outcome::Retry);
+
+        let mut outcomes = Vec::new();
+        for now in (0..).step_by(3600).take(10) {
+            for (delivery, outcome) in
+                process(&mut store, &hash, &policy, now, |_| Ok(500)).unwrap()
+            {
+                assert_eq!(delivery.backend, "audit");
+                outcomes.push(outcome);
+            }
+        }
+        assert_eq!(outcomes.len() as u32, policy.max_attempts - 1);
+        assert_eq!(outcomes.last(), Some(&Outcome::GaveUp));
+    }
+
+    #[test]
+    fn change_event() {
+        let old = ranges(&["151.101.0.0/16", "185.31.16.0/22"]);
+        let new = ranges(&["151.101.0.0/16", "2a04:4e42::/32"]);
+        let version = |first_seen, ranges| Version {
+            hash: content_hash(ranges),
+            first_seen,
+            last_seen: first_seen,
+        };
+        let event = ChangeEvent::new(&version(0, &old), &old, &version(86400, &new), &new);
+
+        assert_eq!(event.event, CHANGED_EVENT);
+        assert_eq!(event.previous.hash, content_hash(&old));
+        assert_ne!(event.previous.hash, event.current.hash);
+        assert_eq!(event.current.first_seen, "1970-01-02T00:00:00Z");
+        assert_eq!(event.ipv4.removed, vec!["185.31.16.0/22".parse().unwrap()]);
+        assert!(event.ipv4.added.is_empty());
+        assert_eq!(event.ipv6.added, vec!["2a04:4e42::/32".parse().unwrap()]);
+    }
+
+    #[test]
+    fn settings_from_lookup() {
+        let settings = WebhookSettings::from_lookup(|key| match key {
+            "webhook_backends" => Some("hooks, audit,".to_owned()),
+            "webhook_max_attempts" => Some("50".to_owned()),
+            _ => None,
+        });
+        assert_eq!(settings.backends, vec!["hooks", "audit"]);
+        assert_eq!(settings.path, "/");
+        assert_eq!(settings.secret, None);
+        assert_eq!(settings.retry.max_attempts, MAX_ATTEMPTS);
+        assert!(!WebhookSettings::from_lookup(|_| None).is_enabled());
+    }
+}
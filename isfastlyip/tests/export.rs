@@ -0,0 +1,38 @@
+use isfastlyip::export::{ExportFormat, EXPORT_FORMATS};
+use isfastlyip::normalize;
+use isfastlyip::FastlyIpList;
+use std::fs;
+use std::path::Path;
+
+/// Compares every export of the fixture list with its golden file. Run with
+/// `UPDATE_GOLDEN=1` to rewrite the golden files after an intended change.
+#[test]
+fn exports_match_golden_files() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+    let body = fs::read(dir.join("fixtures/public-ip-list")).unwrap();
+    let ranges = FastlyIpList::from_json(&body).unwrap().ranges().unwrap();
+    let ranges = normalize::normalize(ranges).ranges;
+
+    for format in EXPORT_FORMATS {
+        let rendered = format.render(&ranges).unwrap();
+        let golden = dir.join("golden").join(format.file_name());
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::write(&golden, &rendered).unwrap();
+        }
+        assert_eq!(
+            rendered,
+            fs::read_to_string(&golden).unwrap(),
+            "{} export differs from {}",
+            format.name(),
+            golden.display()
+        );
+    }
+}
+
+#[test]
+fn export_names() {
+    for format in EXPORT_FORMATS {
+        assert_eq!(ExportFormat::from_name(format.name()), Some(*format));
+    }
+    assert_eq!(ExportFormat::from_name("pf"), None);
+}
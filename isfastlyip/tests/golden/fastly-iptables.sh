@@ -0,0 +1,31 @@
+#!/bin/sh
+# Fastly public IP ranges, from https://api.fastly.com/public-ip-list
+# Only Fastly may reach ports 80 and 443; other traffic is dropped.
+set -e
+iptables -N FASTLY 2>/dev/null || iptables -F FASTLY
+iptables -A FASTLY -s 23.235.32.0/20 -j ACCEPT
+iptables -A FASTLY -s 43.249.72.0/22 -j ACCEPT
+iptables -A FASTLY -s 103.244.50.0/24 -j ACCEPT
+iptables -A FASTLY -s 103.245.222.0/23 -j ACCEPT
+iptables -A FASTLY -s 103.245.224.0/24 -j ACCEPT
+iptables -A FASTLY -s 104.156.80.0/20 -j ACCEPT
+iptables -A FASTLY -s 140.248.64.0/18 -j ACCEPT
+iptables -A FASTLY -s 140.248.128.0/17 -j ACCEPT
+iptables -A FASTLY -s 146.75.0.0/17 -j ACCEPT
+iptables -A FASTLY -s 151.101.0.0/16 -j ACCEPT
+iptables -A FASTLY -s 157.52.64.0/18 -j ACCEPT
+iptables -A FASTLY -s 167.82.0.0/17 -j ACCEPT
+iptables -A FASTLY -s 167.82.128.0/20 -j ACCEPT
+iptables -A FASTLY -s 167.82.160.0/20 -j ACCEPT
+iptables -A FASTLY -s 167.82.224.0/20 -j ACCEPT
+iptables -A FASTLY -s 172.111.64.0/18 -j ACCEPT
+iptables -A FASTLY -s 185.31.16.0/22 -j ACCEPT
+iptables -A FASTLY -s 199.27.72.0/21 -j ACCEPT
+iptables -A FASTLY -s 199.232.0.0/16 -j ACCEPT
+iptables -A FASTLY -j DROP
+iptables -C INPUT -p tcp -m multiport --dports 80,443 -j FASTLY 2>/dev/null || iptables -I INPUT -p tcp -m multiport --dports 80,443 -j FASTLY
+ip6tables -N FASTLY 2>/dev/null || ip6tables -F FASTLY
+ip6tables -A FASTLY -s 2a04:4e40::/32 -j ACCEPT
+ip6tables -A FASTLY -s 2a04:4e42::/32 -j ACCEPT
+ip6tables -A FASTLY -j DROP
+ip6tables -C INPUT -p tcp -m multiport --dports 80,443 -j FASTLY 2>/dev/null || ip6tables -I INPUT -p tcp -m multiport --dports 80,443 -j FASTLY
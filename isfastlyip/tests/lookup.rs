@@ -0,0 +1,76 @@
+use isfastlyip::{addr, service, FastlyIpList, Reply};
+
+const IP_LIST: &str = r#"{
+    "addresses": ["23.235.32.0/20", "151.101.0.0/16"],
+    "ipv6_addresses": ["2a04:4e40::/32", "2a04:4e42::/32"]
+}"#;
+
+fn lookup(path: &str) -> Option<Reply> {
+    let matcher = FastlyIpList::from_json(IP_LIST.as_bytes())
+        .unwrap()
+        .matcher()
+        .unwrap();
+    let ip_addr = addr::parse_path(path).ok()?;
+
+    Some(Reply::json(200, &service::check_ip(ip_addr, &matcher)).unwrap())
+}
+
+#[test]
+fn fastly_addresses() {
+    let reply = lookup("/151.101.230.73").unwrap();
+    assert_eq!(reply.status, 200);
+    assert_eq!(reply.content_type, "application/json");
+    assert_eq!(
+        reply.body,
+        r#"{"is_fastly_ip":true,"ip":"151.101.230.73","family":"ipv4","matched_prefix":"151.101.0.0/16"}"#
+    );
+
+    let reply = lookup("/2a04:4e40:07f6:9100:f8c9:7bf6:e4f5:c5f3").unwrap();
+    assert_eq!(
+        reply.body,
+        r#"{"is_fastly_ip":true,"ip":"2a04:4e40:7f6:9100:f8c9:7bf6:e4f5:c5f3","family":"ipv6","matched_prefix":"2a04:4e40::/32"}"#
+    );
+}
+
+#[test]
+fn other_addresses() {
+    let reply = lookup("/8.8.8.8").unwrap();
+    assert_eq!(
+        reply.body,
+        r#"{"is_fastly_ip":false,"ip":"8.8.8.8","family":"ipv4"}"#
+    );
+
+    let reply = lookup("/240d:1a:7f6:9100:f8c9:7bf6:e4f5:c5f3").unwrap();
+    assert_eq!(
+        reply.body,
+        r#"{"is_fastly_ip":false,"ip":"240d:1a:7f6:9100:f8c9:7bf6:e4f5:c5f3","family":"ipv6"}"#
+    );
+}
+
+#[test]
+fn embedded_ipv4_addresses() {
+    let reply = lookup("/::ffff:151.101.1.1").unwrap();
+    assert_eq!(
+        reply.body,
+        concat!(
+            r#"{"is_fastly_ip":true,"ip":"::ffff:151.101.1.1","family":"ipv6","#,
+            r#""embedded_ipv4":{"embedding":"ipv4_mapped","ip":"151.101.1.1","#,
+            r#""is_fastly_ip":true,"matched_prefix":"151.101.0.0/16"}}"#
+        )
+    );
+
+    let reply = lookup("/2002:0808:0808::").unwrap();
+    assert_eq!(
+        reply.body,
+        concat!(
+            r#"{"is_fastly_ip":false,"ip":"2002:808:808::","family":"ipv6","#,
+            r#""embedded_ipv4":{"embedding":"6to4","ip":"8.8.8.8","is_fastly_ip":false}}"#
+        )
+    );
+}
+
+#[test]
+fn invalid_paths() {
+    assert!(lookup("/").is_none());
+    assert!(lookup("/not-an-ip").is_none());
+}
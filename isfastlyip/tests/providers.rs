@@ -0,0 +1,56 @@
+use isfastlyip::list::IpRanges;
+use isfastlyip::provider::{self, Provider, PROVIDERS};
+use std::fs;
+use std::path::Path;
+
+/// Parses a provider's sources from the fixtures, which are laid out by URL
+/// path so the same directory can also be served as local backends.
+fn load(provider: &Provider) -> IpRanges {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut ranges = IpRanges::default();
+    for source in provider.sources {
+        let body = fs::read(fixtures.join(&source.path[1..])).unwrap();
+        ranges.extend(provider.format.parse(&body).unwrap());
+    }
+    ranges
+}
+
+#[test]
+fn every_provider_parses() {
+    for provider in PROVIDERS {
+        let ranges = load(provider);
+        assert!(
+            !ranges.ipv4.is_empty(),
+            "{} has no IPv4 ranges",
+            provider.name
+        );
+        assert!(
+            !ranges.ipv6.is_empty(),
+            "{} has no IPv6 ranges",
+            provider.name
+        );
+    }
+}
+
+#[test]
+fn owners_from_fixtures() {
+    let matchers: Vec<_> = PROVIDERS
+        .iter()
+        .map(|provider| (provider, load(provider).matcher()))
+        .collect();
+    let owner = |ip: &str| {
+        provider::find_owners(ip.parse().unwrap(), &matchers, vec![])
+            .providers
+            .iter()
+            .map(|found| found.provider)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(owner("151.101.230.73"), vec!["fastly"]);
+    assert_eq!(owner("104.16.1.1"), vec!["cloudflare"]);
+    assert_eq!(owner("3.5.141.1"), vec!["aws"]);
+    assert_eq!(owner("2600:1900:4030::1"), vec!["gcp"]);
+    assert_eq!(owner("13.107.246.10"), vec!["azure"]);
+    assert_eq!(owner("23.1.2.3"), vec!["akamai"]);
+    assert!(owner("192.0.2.1").is_empty());
+}
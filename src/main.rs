@@ -1,103 +1,661 @@
 use anyhow::Result;
-use fastly::http::{header, Method, StatusCode};
-use fastly::{Request, Response};
-use ipnet::{Ipv4Net, Ipv6Net};
-use regex::Regex;
-use std::net::{Ipv4Addr, Ipv6Addr};
+use fastly::http::{header, HeaderValue, Method};
+use fastly::{ConfigStore, ObjectStore, Request, Response};
+use isfastlyip::addr::Query;
+use isfastlyip::cache::{self, CacheSettings};
+use isfastlyip::diff::{ListDiff, ListRef};
+use isfastlyip::export::{ExportFormat, EXPORT_FORMATS};
+use isfastlyip::forwarded::{self, ForwardedChain};
+use isfastlyip::gatekeeper::{self, AddrSource, GatekeeperSettings, Verdict};
+use isfastlyip::history::{self, History, SnapshotStore, MIN_HASH_PREFIX};
+use isfastlyip::provider::{self, Provider, PROVIDERS};
+use isfastlyip::render::Format;
+use isfastlyip::router::{self, Route};
+use isfastlyip::validate::{self, LoadedList, ValidatedList};
+use isfastlyip::webhook::{self, ChangeEvent, Outcome, WebhookSettings};
+use isfastlyip::{
+    addr, batch, coverage, normalize, service, snapshot, timestamp, FastlyIpList, IpRanges, Reply,
+};
+use std::convert::TryFrom;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 const BACKEND_NAME: &str = "fastlyapi";
 
-#[derive(serde::Deserialize, Debug)]
-struct FastlyIpList {
-    addresses: Vec<String>,
-    ipv6_addresses: Vec<String>,
+/// Converts a reply rendered by the library into a Compute@Edge response.
+fn into_response(reply: Reply) -> Response {
+    let mut resp = Response::from_status(reply.status)
+        .with_header(header::CONTENT_TYPE, reply.content_type)
+        .with_body(reply.body);
+    for (name, value) in reply.headers {
+        resp.set_header(name, value);
+    }
+    resp
 }
 
-#[derive(serde::Serialize)]
-struct IpCheckResult {
-    is_fastly_ip: bool,
+fn text_response(status: u16, body: &str) -> Result<Response> {
+    Ok(into_response(Reply::text(status, body)))
 }
 
-#[fastly::main]
-fn main(req: Request) -> Result<Response> {
-    // We can filter requests that have unexpected methods.
-    const VALID_METHODS: [Method; 1] = [Method::GET];
-    if !(VALID_METHODS.contains(req.get_method())) {
-        return Ok(
-            Response::from_status(StatusCode::NOT_FOUND).with_body("Only GET method is allowed")
-        );
-    }
-
-    // Regular expression to check if a string is a IPv6 or IPv4 address
-    let re_ip46 = Regex::new("^/((?:[0-9]{1,3}.){3}[0-9]{1,3}|(([a-f0-9:]+:+)+[a-f0-9]+))$")?;
+/// Picks the response format from the `format` query parameter or `Accept`.
+fn negotiate_format(req: &Request) -> Result<Format> {
+    Format::negotiate(req.get_query_str(), req.get_header_str(header::ACCEPT))
+}
 
-    let path = req.get_path();
-    if !re_ip46.is_match(path) {
-        return Ok(
-            Response::from_status(StatusCode::NOT_FOUND).with_body("Valid IP address not found")
-        );
+/// Reads the cache settings from the config store, falling back to the
+/// defaults when the config store does not exist.
+fn cache_settings() -> CacheSettings {
+    match ConfigStore::try_open(cache::CONFIG_DICTIONARY) {
+        Ok(config) => CacheSettings::from_lookup(|key| config.get(key)),
+        Err(_) => CacheSettings::default(),
     }
+}
 
-    // Save the IP request from client
-    let ip_addr = path[1..].to_owned();
-
-    // Get IP list info
+/// Fetches Fastly's published IP list through the cache, or straight from
+/// the origin with `pass`, checking the response before trusting it. Also
+/// returns the `Age` of the cached copy.
+fn fetch_ip_list(settings: &CacheSettings, pass: bool) -> Result<(ValidatedList, Option<u32>)> {
     let req_backend = Request::get("https://dummy/public-ip-list")
-        .with_ttl(60 * 60 * 24 * 7)
+        .with_pass(pass)
+        .with_ttl(settings.ttl)
+        .with_stale_while_revalidate(settings.stale_while_revalidate)
+        .with_surrogate_key(HeaderValue::from_static(cache::SURROGATE_KEY))
         .with_header(header::HOST, "api.fastly.com");
     let mut resp = req_backend.send(BACKEND_NAME)?;
 
-    let ip_list = resp.take_body_json::<FastlyIpList>()?;
+    let age = cache::parse_age(resp.get_header_str(header::AGE));
+    let content_type = resp.get_header_str(header::CONTENT_TYPE).map(str::to_owned);
+    let body = resp.take_body_bytes();
+    let validated =
+        validate::validate_response(resp.get_status().as_u16(), content_type.as_deref(), &body)?;
+    Ok((validated, age))
+}
+
+/// The last version recorded in the history, when it is recent enough to be
+/// served in place of a list that cannot be fetched.
+fn stale_ip_list(settings: &CacheSettings, fallback_reason: &str) -> Result<Option<LoadedList>> {
+    let (version, ranges) = match open_history()?.latest()? {
+        Some(latest) => latest,
+        None => return Ok(None),
+    };
+    let age = unix_now()? - version.last_seen;
+    if !settings.serves_if_error(age) {
+        return Ok(None);
+    }
+    Ok(Some(LoadedList::stale_if_error(
+        &version,
+        ranges,
+        u32::try_from(age.max(0)).unwrap_or(u32::MAX),
+        fallback_reason.to_owned(),
+    )))
+}
 
-    if let Ok(ipv4) = ip_addr.parse::<Ipv4Addr>() {
-        for ipv4net in ip_list.addresses {
-            let net: Ipv4Net = ipv4net.parse()?;
-            if net.contains(&ipv4) {
-                return Ok(Response::from_status(StatusCode::OK)
-                    .with_body_json(&IpCheckResult { is_fastly_ip: true })?);
+/// Loads the live Fastly IP list. When it cannot be fetched or is not
+/// trusted, falls back to the last recorded version within the
+/// stale-if-error window, or else the embedded snapshot.
+fn load_ip_list() -> LoadedList {
+    let settings = cache_settings();
+    match fetch_ip_list(&settings, false) {
+        Ok((validated, age)) => {
+            for diagnostic in &validated.diagnostics {
+                eprintln!("Skipped IP list entry: {:?}", diagnostic);
             }
+            LoadedList::live(validated).with_age(age, &settings)
         }
-    } else if let Ok(ipv6) = ip_addr.parse::<Ipv6Addr>() {
-        for ipv6net in ip_list.ipv6_addresses {
-            let net: Ipv6Net = ipv6net.parse()?;
-            if net.contains(&ipv6) {
-                return Ok(Response::from_status(StatusCode::OK)
-                    .with_body_json(&IpCheckResult { is_fastly_ip: true })?);
+        Err(err) => match stale_ip_list(&settings, &err.to_string()) {
+            Ok(Some(ip_list)) => {
+                eprintln!("Using the last recorded IP list: {}", err);
+                ip_list
+            }
+            result => {
+                if let Err(history_err) = result {
+                    eprintln!("Failed to read the IP list history: {}", history_err);
+                }
+                eprintln!("Using the embedded snapshot of the IP list: {}", err);
+                LoadedList::snapshot(err.to_string())
             }
+        },
+    }
+}
+
+/// The history of the IP list, kept in an object store.
+struct KvStore(ObjectStore);
+
+impl SnapshotStore for KvStore {
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.0.lookup(key)?.map(|body| body.into_string()))
+    }
+
+    fn put(&mut self, key: &str, value: String) -> Result<()> {
+        Ok(self.0.insert(key, value)?)
+    }
+}
+
+fn open_store() -> Result<KvStore> {
+    match ObjectStore::open(history::HISTORY_STORE)? {
+        Some(store) => Ok(KvStore(store)),
+        None => anyhow::bail!("Object store {} does not exist", history::HISTORY_STORE),
+    }
+}
+
+fn open_history() -> Result<History<KvStore>> {
+    Ok(History::new(open_store()?))
+}
+
+fn unix_now() -> Result<i64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64)
+}
+
+/// Reads the webhook settings from the config store.
+fn webhook_settings() -> WebhookSettings {
+    match ConfigStore::try_open(cache::CONFIG_DICTIONARY) {
+        Ok(config) => WebhookSettings::from_lookup(|key| config.get(key)),
+        Err(_) => WebhookSettings::from_lookup(|_| None),
+    }
+}
+
+/// Records `ranges` in the history, queueing notifications for the webhooks
+/// when they are a new version. Returns whether they are.
+fn record_history(ranges: &IpRanges) -> Result<bool> {
+    let now = unix_now()?;
+    let mut history = open_history()?;
+    if !history.record(ranges, now)? {
+        return Ok(false);
+    }
+    let settings = webhook_settings();
+    if !settings.is_enabled() {
+        return Ok(true);
+    }
+
+    let versions = history.versions()?;
+    if let [.., previous, current] = versions.as_slice() {
+        if let Some((_, previous_ranges)) = history.get(&previous.hash)? {
+            webhook::enqueue(
+                &mut open_store()?,
+                &ChangeEvent::new(previous, &previous_ranges, current, ranges),
+                &settings.backends,
+                now,
+            )?;
+        }
+    }
+    Ok(true)
+}
+
+/// Handles `POST /v1/refresh`, fetching the list from the origin and
+/// recording it in the history. Lookups only read the cached list, so this is
+/// what keeps the history current; it is meant to be called on a schedule.
+/// The webhooks are notified once it has answered.
+fn handle_refresh(req: &Request) -> Result<(Response, Followup)> {
+    let token = ConfigStore::try_open(cache::CONFIG_DICTIONARY)
+        .ok()
+        .and_then(|config| config.get(history::REFRESH_TOKEN_KEY));
+    if !history::authorizes_refresh(token.as_deref(), req.get_header_str(header::AUTHORIZATION)) {
+        return Ok((
+            text_response(403, "Refreshing requires the refresh token")?,
+            Followup::Nothing,
+        ));
+    }
+
+    // Deliveries left over from earlier refreshes are retried even when
+    // this one fails.
+    let validated = match fetch_ip_list(&cache_settings(), true) {
+        Ok((validated, _)) => validated,
+        Err(err) => {
+            return Ok((
+                text_response(502, &format!("Failed to fetch the IP list: {}", err))?,
+                Followup::DeliverWebhooks,
+            ))
         }
+    };
+    let ranges = LoadedList::live(validated).ranges;
+    let body = if record_history(&ranges)? {
+        format!("Recorded version {}", history::content_hash(&ranges))
     } else {
-        return Ok(
-            Response::from_status(StatusCode::NOT_FOUND).with_body("Valid IP address not found")
-        );
+        "The IP list is unchanged".to_owned()
+    };
+    Ok((text_response(200, &body)?, Followup::DeliverWebhooks))
+}
+
+/// Makes one attempt at every due webhook delivery of the versions first
+/// seen within the delivery window. Failed ones wait for a later refresh.
+fn deliver_webhooks() -> Result<()> {
+    let settings = webhook_settings();
+    let now = unix_now()?;
+    let versions = open_history()?.versions()?;
+    let mut store = open_store()?;
+    for version in versions
+        .iter()
+        .rev()
+        .take_while(|version| now - version.first_seen <= webhook::DELIVERY_WINDOW)
+    {
+        let attempted = webhook::process(
+            &mut store,
+            &version.hash,
+            &settings.retry,
+            now,
+            |delivery| {
+                let mut req =
+                    Request::post(format!("https://{}{}", delivery.backend, settings.path))
+                        .with_header(header::CONTENT_TYPE, "application/json")
+                        .with_header("x-isfastlyip-event", webhook::CHANGED_EVENT)
+                        .with_header(
+                            "x-isfastlyip-delivery-attempt",
+                            (delivery.attempts + 1).to_string(),
+                        )
+                        .with_body(delivery.body.clone());
+                if let Some(secret) = &settings.secret {
+                    let signature = webhook::sign(secret, now, delivery.body.as_bytes());
+                    req.set_header(webhook::SIGNATURE_HEADER, signature);
+                }
+                Ok(req.send(delivery.backend.as_str())?.get_status().as_u16())
+            },
+        )?;
+        for (delivery, outcome) in attempted {
+            let error = delivery.last_error.unwrap_or_default();
+            match outcome {
+                Outcome::Delivered => eprintln!(
+                    "Notified webhook {} of version {} after {} attempts",
+                    delivery.backend, version.hash, delivery.attempts
+                ),
+                Outcome::Retry => eprintln!(
+                    "Failed to notify webhook {} of version {}, retrying later: {}",
+                    delivery.backend, version.hash, error
+                ),
+                Outcome::GaveUp => eprintln!(
+                    "Gave up notifying webhook {} of version {} after {} attempts: {}",
+                    delivery.backend, version.hash, delivery.attempts, error
+                ),
+            }
+        }
     }
+    Ok(())
+}
 
-    return Ok(
-        Response::from_status(StatusCode::OK).with_body_json(&IpCheckResult {
-            is_fastly_ip: false,
-        })?,
-    );
+/// Loads the list a lookup asks for: the version that was valid at its `at`
+/// parameter, an RFC 3339 timestamp, or else the current one.
+fn requested_list(req: &Request) -> std::result::Result<LoadedList, Reply> {
+    let at = match addr::query_param(req.get_query_str(), "at") {
+        Some(at) => at,
+        None => return Ok(load_ip_list()),
+    };
+    let time = timestamp::parse_rfc3339(&at).map_err(|err| Reply::text(400, &err.to_string()))?;
+
+    match open_history().and_then(|history| history.at(time)) {
+        Ok(Some((version, ranges))) => Ok(LoadedList::historical(&version, ranges)),
+        Ok(None) => Err(Reply::text(
+            404,
+            &format!(
+                "No version of the IP list is known at {}",
+                timestamp::format_rfc3339(time)
+            ),
+        )),
+        Err(err) => Err(Reply::text(503, &err.to_string())),
+    }
+}
+
+/// Fetches and parses every document a provider publishes its ranges in.
+fn fetch_ranges(provider: &Provider) -> Result<IpRanges> {
+    let mut ranges = IpRanges::default();
+    for source in provider.sources {
+        let mut resp = Request::get(format!("https://{}{}", source.host, source.path))
+            .with_ttl(provider.ttl)
+            .with_header(header::HOST, source.host)
+            .send(provider.backend)?;
+        ranges.extend(provider.format.parse(&resp.take_body_bytes())?);
+    }
+    Ok(ranges)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    #[test]
-    fn ipv4contains() {
-        let net: Ipv4Net = "151.101.0.0/16".parse().unwrap();
-        let ip_yes: Ipv4Addr = "151.101.0.1".parse().unwrap();
-        let ip_no: Ipv4Addr = "151.102.0.0".parse().unwrap();
+/// Handles `GET /v1/providers/{ip}`, reporting which CDNs and clouds own the address.
+fn handle_providers(ip: &str) -> Result<Response> {
+    let ip_addr = match addr::parse_path(ip) {
+        Ok(ip_addr) => ip_addr,
+        Err(err) => return text_response(400, &err.to_string()),
+    };
 
-        assert!(net.contains(&ip_yes));
-        assert!(!net.contains(&ip_no));
+    let mut matchers = Vec::new();
+    let mut unavailable = Vec::new();
+    for provider in PROVIDERS {
+        if provider.name == provider::FASTLY.name {
+            matchers.push((provider, load_ip_list().matcher()));
+            continue;
+        }
+        match fetch_ranges(provider) {
+            Ok(ranges) => matchers.push((provider, ranges.matcher())),
+            Err(err) => {
+                eprintln!("Failed to load {} ranges: {}", provider.name, err);
+                unavailable.push(provider.name);
+            }
+        }
     }
 
-    #[test]
-    fn ipv6contains() {
-        let net: Ipv6Net = "2a04:4e40::/32".parse().unwrap();
-        let ip_yes: Ipv6Addr = "2a04:4e40:7f6:9100:f8c9:7bf6:e4f5:c5f3".parse().unwrap();
-        let ip_no: Ipv6Addr = "240d:1a:7f6:9100:f8c9:7bf6:e4f5:c5f3".parse().unwrap();
+    let result = provider::find_owners(ip_addr, &matchers, unavailable);
+    Ok(into_response(Reply::json(200, &result)?))
+}
 
-        assert!(net.contains(&ip_yes));
-        assert!(!net.contains(&ip_no));
+/// Loads a version of the list to diff, labelled for display, or `None` when
+/// the history has no such version.
+fn load_list_ref(list_ref: ListRef) -> Result<Option<(String, IpRanges)>> {
+    let (label, ranges) = match list_ref {
+        ListRef::Live => {
+            let (validated, _) = fetch_ip_list(&cache_settings(), false)?;
+            ("live".to_owned(), validated.ranges)
+        }
+        ListRef::Snapshot => (
+            format!("snapshot {}", snapshot::SNAPSHOT_DATE),
+            snapshot::ranges(),
+        ),
+        ListRef::Version(prefix) => match open_history()?.get(&prefix)? {
+            Some((version, ranges)) => (
+                format!(
+                    "version {}, first seen {}",
+                    &version.hash[..MIN_HASH_PREFIX],
+                    timestamp::format_rfc3339(version.first_seen)
+                ),
+                ranges,
+            ),
+            None => return Ok(None),
+        },
+    };
+    Ok(Some((label, normalize::normalize(ranges).ranges)))
+}
+
+/// Handles `GET /v1/diff?from=..&to=..`, comparing two versions of the list.
+/// Each side is `live`, `snapshot` or a version number from the history, and
+/// defaults to the snapshot and the live list respectively.
+fn handle_diff(req: &Request, format: Format) -> Result<Response> {
+    let mut sides = Vec::new();
+    for (param, default) in &[("from", ListRef::Snapshot), ("to", ListRef::Live)] {
+        let list_ref = match addr::query_param(req.get_query_str(), param) {
+            Some(value) => match ListRef::parse(&value) {
+                Ok(list_ref) => list_ref,
+                Err(err) => return text_response(400, &err.to_string()),
+            },
+            None => default.clone(),
+        };
+        match load_list_ref(list_ref) {
+            Ok(Some(side)) => sides.push(side),
+            Ok(None) => return text_response(404, "No such version of the IP list"),
+            Err(err) => return text_response(503, &err.to_string()),
+        }
+    }
+
+    let (to, to_ranges) = sides.pop().unwrap();
+    let (from, from_ranges) = sides.pop().unwrap();
+    let diff = ListDiff::new(from, &from_ranges, to, &to_ranges);
+    Ok(into_response(Reply::render(200, &diff, format)?))
+}
+
+/// Handles `GET /v1/export/{format}`, rendering the list as firewall or proxy configuration.
+fn handle_export(name: &str) -> Result<Response> {
+    let format = match ExportFormat::from_name(name) {
+        Some(format) => format,
+        None => {
+            let names: Vec<_> = EXPORT_FORMATS.iter().map(|format| format.name()).collect();
+            return text_response(
+                404,
+                &format!(
+                    "Unknown export format, expected one of: {}",
+                    names.join(", ")
+                ),
+            );
+        }
+    };
+
+    let ip_list = load_ip_list();
+    Ok(into_response(
+        format
+            .reply(&ip_list.ranges)?
+            .with_headers(ip_list.headers()),
+    ))
+}
+
+/// Handles `POST /v1/batch`, checking every address of the body against one fetch of the list.
+fn handle_batch(mut req: Request) -> Result<Response> {
+    let format = match negotiate_format(&req) {
+        Ok(format) => format,
+        Err(err) => return text_response(400, &err.to_string()),
+    };
+
+    // Refuse an announced oversized body before reading any of it.
+    let length = req
+        .get_header_str(header::CONTENT_LENGTH)
+        .and_then(|length| length.trim().parse::<usize>().ok());
+    if matches!(length, Some(length) if length > batch::MAX_BODY_SIZE) {
+        let err = batch::BodyError::TooLarge;
+        return text_response(err.status(), &err.to_string());
+    }
+    let content_type = req.get_header_str(header::CONTENT_TYPE).map(str::to_owned);
+    let body = match batch::read_body(req.take_body()) {
+        Ok(body) => body,
+        Err(err) => return text_response(err.status(), &err.to_string()),
+    };
+    let items = match batch::parse_body(&body, content_type.as_deref()) {
+        Ok(items) => items,
+        Err(err) => return text_response(400, &err.to_string()),
+    };
+
+    let ip_list = match requested_list(&req) {
+        Ok(ip_list) => ip_list,
+        Err(reply) => return Ok(into_response(reply)),
+    };
+    let matcher = ip_list.matcher();
+    let results = batch::check_batch(&items, &matcher);
+    Ok(into_response(
+        Reply::render(200, &results, format)?.with_headers(ip_list.headers()),
+    ))
+}
+
+/// Handles `/v1/forwarded`: `GET` and `HEAD` analyze the forwarding headers
+/// of the request itself, `POST` those given as a JSON body.
+fn handle_forwarded(mut req: Request) -> Result<Response> {
+    let chain = if req.get_method() == Method::POST {
+        match req.take_body_json::<ForwardedChain>() {
+            Ok(chain) => chain,
+            Err(err) => return text_response(400, &err.to_string()),
+        }
+    } else {
+        ForwardedChain {
+            x_forwarded_for: req.get_header_str("x-forwarded-for").map(str::to_owned),
+            fastly_client_ip: req.get_header_str("fastly-client-ip").map(str::to_owned),
+            peer: req.get_client_ip_addr(),
+        }
+    };
+
+    let ip_list = load_ip_list();
+    let matcher = ip_list.matcher();
+    let analysis = forwarded::analyze_chain(&chain, &matcher);
+    Ok(into_response(
+        Reply::json(200, &analysis)?.with_headers(ip_list.headers()),
+    ))
+}
+
+/// Answers a lookup of an address, network or range, against the version
+/// of the list asked for with `at`.
+fn handle_lookup(req: &Request, query: Query, format: Format) -> Result<Response> {
+    let ip_list = match requested_list(req) {
+        Ok(ip_list) => ip_list,
+        Err(reply) => return Ok(into_response(reply)),
+    };
+    let matcher = ip_list.matcher();
+    let reply = match query {
+        Query::Addr(ip_addr) => Reply::render(200, &service::check_ip(ip_addr, &matcher), format)?,
+        Query::Net(net) => Reply::render(200, &coverage::check_net(net, &matcher), format)?,
+        Query::Range(start, end) => match coverage::check_range(start, end, &matcher) {
+            Ok(result) => Reply::render(200, &result, format)?,
+            Err(err) => Reply::text(400, &err.to_string()),
+        },
+    };
+    Ok(into_response(reply.with_headers(ip_list.headers())))
+}
+
+/// Handles `GET /v1/ranges`, serving the list in the format Fastly publishes
+/// it in.
+fn handle_ranges(req: &Request) -> Result<Response> {
+    let ip_list = match requested_list(req) {
+        Ok(ip_list) => ip_list,
+        Err(reply) => return Ok(into_response(reply)),
+    };
+    Ok(into_response(
+        Reply::json(200, &FastlyIpList::from_ranges(&ip_list.ranges))?
+            .with_headers(ip_list.headers()),
+    ))
+}
+
+/// Reads the gatekeeper settings from the config store. Without the
+/// store the service answers lookups.
+fn gatekeeper_settings() -> Result<Option<GatekeeperSettings>> {
+    match ConfigStore::try_open(cache::CONFIG_DICTIONARY) {
+        Ok(config) => GatekeeperSettings::from_lookup(|key| config.get(key)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Proxies the request to the gatekeeper backend, annotated with whether its
+/// address belongs to Fastly or the other configured providers, or blocks it.
+fn handle_gatekeeper(mut req: Request, settings: &GatekeeperSettings) -> Result<Response> {
+    let addr = match &settings.addr_source {
+        AddrSource::Client => req.get_client_ip_addr(),
+        AddrSource::Header(name) => req
+            .get_header_str(name.as_str())
+            .and_then(gatekeeper::parse_header_addr),
+    };
+
+    let fastly = load_ip_list().matcher();
+    let mut fetched = Vec::new();
+    for provider in &settings.providers {
+        if provider.name == provider::FASTLY.name {
+            fetched.push(None);
+            continue;
+        }
+        // Deciding without a provider's ranges would let its addresses
+        // through a deny list, or block them from an allow list.
+        match fetch_ranges(provider) {
+            Ok(ranges) => fetched.push(Some(ranges.matcher())),
+            Err(err) => {
+                eprintln!("Failed to load {} ranges: {}", provider.name, err);
+                return text_response(
+                    503,
+                    &format!("The {} ranges are unavailable", provider.name),
+                );
+            }
+        }
+    }
+
+    let providers: Vec<_> = settings
+        .providers
+        .iter()
+        .zip(&fetched)
+        .map(|(provider, matcher)| (*provider, matcher.as_ref().unwrap_or(&fastly)))
+        .collect();
+    let verdict = Verdict::judge(addr, &fastly, &providers);
+    if !verdict.forwards(settings.action) {
+        return Ok(into_response(verdict.rejection()));
+    }
+    for name in gatekeeper::ANNOTATION_HEADERS {
+        req.remove_header(*name);
+    }
+    for (name, value) in verdict.headers() {
+        req.set_header(name, value);
+    }
+    Ok(req.send(settings.backend.as_str())?)
+}
+
+/// Answers the endpoints taking `GET` and `HEAD`, in the negotiated format.
+fn handle_get(req: &Request, route: Route) -> Result<Response> {
+    let format = match negotiate_format(req) {
+        Ok(format) => format,
+        Err(err) => return text_response(400, &err.to_string()),
+    };
+
+    match route {
+        Route::Me => {
+            // Check the address the request itself came from
+            let ip_addr = match req.get_client_ip_addr() {
+                Some(ip_addr) => ip_addr,
+                None => return text_response(404, "Client IP address not available"),
+            };
+            let ip_list = load_ip_list();
+            let matcher = ip_list.matcher();
+            let result = service::check_ip(ip_addr, &matcher);
+            Ok(into_response(
+                Reply::render(200, &result, format)?.with_headers(ip_list.headers()),
+            ))
+        }
+        Route::Diagnostics => {
+            let ip_list = load_ip_list();
+            Ok(into_response(
+                Reply::json(200, &ip_list.report())?.with_headers(ip_list.headers()),
+            ))
+        }
+        Route::Health => Ok(into_response(Reply::json(200, &load_ip_list().health())?)),
+        Route::Ranges => handle_ranges(req),
+        Route::Diff => handle_diff(req, format),
+        Route::Providers(ip) => handle_providers(ip),
+        Route::Export(name) => handle_export(name),
+        Route::Ip(value) => match addr::parse_path(value) {
+            Ok(ip_addr) => handle_lookup(req, Query::Addr(ip_addr), format),
+            Err(err) => text_response(400, &err.to_string()),
+        },
+        Route::Cidr(value) => match addr::parse_query(value) {
+            Ok(Query::Net(net)) => handle_lookup(req, Query::Net(net), format),
+            Ok(_) => text_response(400, "Expected a network in CIDR notation"),
+            Err(err) => text_response(400, &err.to_string()),
+        },
+        Route::Lookup(path) => match addr::parse_query(path) {
+            Ok(query) => handle_lookup(req, query, format),
+            Err(err) => text_response(400, &err.to_string()),
+        },
+        Route::Batch | Route::Forwarded | Route::Refresh => unreachable!("not a GET endpoint"),
+    }
+}
+
+/// What is left to do once the response has been sent.
+enum Followup {
+    Nothing,
+    DeliverWebhooks,
+}
+
+fn handle_request(req: Request) -> Result<(Response, Followup)> {
+    match gatekeeper_settings() {
+        Ok(Some(settings)) => return Ok((handle_gatekeeper(req, &settings)?, Followup::Nothing)),
+        Ok(None) => {}
+        Err(err) => {
+            eprintln!("Invalid gatekeeper configuration: {}", err);
+            return Ok((
+                text_response(500, "Invalid gatekeeper configuration")?,
+                Followup::Nothing,
+            ));
+        }
+    }
+
+    let route = match router::route(req.get_method().as_str(), req.get_path()) {
+        Ok(route) => route,
+        Err(err) => return Ok((into_response(err.reply()), Followup::Nothing)),
+    };
+    let resp = match route {
+        Route::Batch => handle_batch(req),
+        Route::Forwarded => handle_forwarded(req),
+        Route::Refresh => return handle_refresh(&req),
+        route => handle_get(&req, route),
+    }?;
+    Ok((resp, Followup::Nothing))
+}
+
+fn main() -> Result<()> {
+    let (resp, followup) = match handle_request(Request::from_client()) {
+        Ok(handled) => handled,
+        Err(err) => (
+            into_response(Reply::text(500, &err.to_string())),
+            Followup::Nothing,
+        ),
+    };
+    resp.send_to_client();
+
+    // The client already has its answer, so slow webhooks cannot hold it up.
+    if let Followup::DeliverWebhooks = followup {
+        if let Err(err) = deliver_webhooks() {
+            eprintln!("Failed to deliver the webhooks: {}", err);
+        }
     }
+    Ok(())
 }